@@ -1,3 +1,4 @@
+use std::time::{SystemTime, UNIX_EPOCH};
 use bevy_ecs::event::Events;
 use winit::{
     event::*,
@@ -5,18 +6,49 @@ use winit::{
     window::WindowBuilder,
 };
 use winit::window::Fullscreen;
-use bevy_ecs::prelude::*;
-use bevy_ecs::schedule::RunOnce;
 use bevy_ecs::world::WorldCell;
-use wgpu::{Device, Surface, SurfaceConfiguration};
+use bevy_input::Input;
+use bevy_input::keyboard::KeyCode;
+use bevy_input::mouse::{MouseButton, MouseButtonInput, MouseMotion, MouseWheel};
+use glam::Vec2;
+use image::RgbaImage;
+use wgpu::{Device, Queue, Surface, SurfaceConfiguration};
 use winit::dpi::PhysicalSize;
-use crate::{core, game};
-use crate::core::time::Time;
+use crate::core;
+use crate::core::camera_controller::CameraControllerPlugin;
+use crate::core::input::InputPlugin;
+use crate::core::recording;
+use crate::core::recording::{InputMode, InputReplay, RecordingPlugin};
+use crate::core::time::TimePlugin;
+use crate::core::window::{CursorLeft, CursorMoved, WindowResized};
 use crate::renderer;
+use crate::renderer::capture::{CaptureFrame, FrameCapture};
+use crate::renderer::offscreen::OffscreenTarget;
+use crate::renderer::RendererPlugin;
+
+mod plugin;
+pub mod stage;
+
+pub use plugin::{App, Plugin, EVENT_UPDATE_SYSTEM};
 
 pub const ASSETS_DIR: &str = "assets";
 
-pub async fn run() {
+/// Everything the engine provides: time, input and its recording, the camera controls and the
+/// renderer. A game adds this and then its own plugins.
+pub struct EnginePlugin;
+
+impl Plugin for EnginePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(TimePlugin)
+            .add_plugin(InputPlugin)
+            .add_plugin(RecordingPlugin)
+            .add_plugin(CameraControllerPlugin)
+            .add_plugin(RendererPlugin);
+    }
+}
+
+/// Opens a fullscreen window and runs `app` in it until it is closed
+pub async fn run(mut app: App, input_mode: InputMode) {
     env_logger::init();
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
@@ -27,59 +59,38 @@ pub async fn run() {
     let (instance, surface, adapter, size) = pollster::block_on(renderer::initialize_wgpu(&window));
     let (device, queue, surface_config) = pollster::block_on(renderer::initialize_renderer(&adapter, &surface, &size));
 
-    let mut world = World::new();
-    world.insert_resource(core::systems::Count(0));
-    world.insert_resource(instance);
-    world.insert_resource(device);
-    world.insert_resource(queue);
-    world.insert_resource(surface);
-    world.insert_resource(surface_config);
-    world.init_resource::<Time>();
-    world.init_resource::<Events<bevy_input::keyboard::KeyboardInput>>();
-    let mut schedule = Schedule::default();
-    schedule
-        .add_stage(
-            "engine_startup",
-            SystemStage::parallel()
-                .with_run_criteria(RunOnce::default())
-                .with_system(core::systems::renderer_startup)
-        )
-        .add_stage(
-            "game_startup",
-            SystemStage::parallel()
-                .with_run_criteria(RunOnce::default())
-                .with_system(game::start)
-        )
-        .add_stage(
-            "first",
-            SystemStage::parallel()
-                .with_system(core::time::time_system)
-                .with_system(Events::<bevy_input::keyboard::KeyboardInput>::update_system)
-        )
-        .add_stage(
-        "update",
-       SystemStage::parallel()
-           .with_system(core::systems::counter)
-           .with_system(core::systems::camera_control)
-        )
-        .add_stage(
-            "render",
-            SystemStage::parallel()
-                .with_system(core::systems::render)
-        );
+    app.insert_resource(instance)
+        .insert_resource(device)
+        .insert_resource(queue)
+        .insert_resource(surface)
+        .insert_resource(surface_config);
+    if let Err(error) = recording::insert_input_mode(&mut app.world, &input_mode) {
+        log::error!("{}", error);
+        return;
+    }
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::MainEventsCleared => {
-            schedule.run(&mut world);
+            app.update();
             window.request_redraw();
         },
         Event::RedrawRequested(window_id) if window_id == window.id() => {
         },
+        // A replay provides all input until it ends, so live input is dropped meanwhile
+        Event::DeviceEvent {
+            event: DeviceEvent::MouseMotion { delta },
+            ..
+        } if !app.world.contains_resource::<InputReplay>() => {
+            // Raw device motion keeps working while the cursor is grabbed, unlike cursor positions
+            let mut mouse_motion_events = app.world.get_resource_mut::<Events<MouseMotion>>().unwrap();
+            mouse_motion_events.send(MouseMotion { delta: Vec2::new(delta.0 as f32, delta.1 as f32) });
+        },
         Event::WindowEvent {
             ref event,
             window_id,
         } if window_id == window.id() => {
-            let world_cell = world.cell();
+            let world_cell = app.world.cell();
+            let replaying = world_cell.get_resource::<InputReplay>().is_some();
             match event {
                 WindowEvent::CloseRequested | WindowEvent::KeyboardInput {
                     input:
@@ -91,12 +102,63 @@ pub async fn run() {
                     ..
                 } => *control_flow = ControlFlow::Exit,
                 WindowEvent::KeyboardInput {
-                    ref input,
+                    input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F12),
+                        ..
+                    },
+                    ..
+                } => {
+                    let mut capture_requests = world_cell.get_resource_mut::<Events<CaptureFrame>>().unwrap();
+                    capture_requests.send(CaptureFrame);
+                },
+                WindowEvent::KeyboardInput {
+                    input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F11),
+                        ..
+                    },
                     ..
                 } => {
+                    let mut frame_capture = world_cell.get_resource_mut::<FrameCapture>().unwrap();
+                    if frame_capture.is_recording_sequence() {
+                        frame_capture.stop_sequence();
+                    } else {
+                        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                        frame_capture.start_sequence(&format!("sequence_{}", timestamp.as_secs()));
+                    }
+                },
+                WindowEvent::KeyboardInput {
+                    ref input,
+                    ..
+                } if !replaying => {
                     let mut keyboard_input_events =
                         world_cell.get_resource_mut::<Events<bevy_input::keyboard::KeyboardInput>>().unwrap();
-                    keyboard_input_events.send(core::input::convert_winit_keyboard_input(input));                }
+                    keyboard_input_events.send(core::input::convert_winit_keyboard_input(input));
+                }
+                WindowEvent::MouseInput { state, button, .. } if !replaying => {
+                    let mut mouse_button_events = world_cell.get_resource_mut::<Events<MouseButtonInput>>().unwrap();
+                    mouse_button_events.send(core::input::convert_winit_mouse_button_input(*state, *button));
+                },
+                WindowEvent::MouseWheel { delta, .. } if !replaying => {
+                    let mut mouse_wheel_events = world_cell.get_resource_mut::<Events<MouseWheel>>().unwrap();
+                    mouse_wheel_events.send(core::input::convert_winit_mouse_wheel(*delta));
+                },
+                WindowEvent::CursorMoved { position, .. } if !replaying => {
+                    let mut cursor_moved_events = world_cell.get_resource_mut::<Events<CursorMoved>>().unwrap();
+                    cursor_moved_events.send(CursorMoved { position: Vec2::new(position.x as f32, position.y as f32) });
+                },
+                WindowEvent::CursorLeft { .. } if !replaying => {
+                    let mut cursor_left_events = world_cell.get_resource_mut::<Events<CursorLeft>>().unwrap();
+                    cursor_left_events.send(CursorLeft);
+                },
+                WindowEvent::Focused(false) if !replaying => {
+                    // Releases while another window has focus never reach us, so they would stay held
+                    release_all(&mut world_cell.get_resource_mut::<Input<KeyCode>>().unwrap());
+                    release_all(&mut world_cell.get_resource_mut::<Input<MouseButton>>().unwrap());
+                },
                 WindowEvent::Resized(new_size) => {
                     resize_window(world_cell, new_size)
                 },
@@ -110,6 +172,13 @@ pub async fn run() {
     });
 }
 
+fn release_all<T: Copy + Eq + std::hash::Hash>(input: &mut Input<T>) {
+    let held: Vec<T> = input.get_pressed().copied().collect();
+    for held_input in held {
+        input.release(held_input);
+    }
+}
+
 fn resize_window(world_cell: WorldCell, new_size: &PhysicalSize<u32>) {
     if new_size.width > 0 && new_size.height > 0 {
         let device = world_cell.get_resource_mut::<Device>().unwrap();
@@ -118,6 +187,111 @@ fn resize_window(world_cell: WorldCell, new_size: &PhysicalSize<u32>) {
         config.width = new_size.width;
         config.height = new_size.height;
         surface.configure(&device, &config);
+
+        let mut resize_events = world_cell.get_resource_mut::<Events<WindowResized>>().unwrap();
+        resize_events.send(WindowResized { width: new_size.width, height: new_size.height });
     }
 }
 
+/// Runs `app` for `frames` frames without a window, rendering into an offscreen target of the
+/// given size, and returns the last frame. Returns `None` if no adapter, not even a software
+/// fallback, is available.
+pub fn run_headless(mut app: App, width: u32, height: u32, frames: usize) -> Option<RgbaImage> {
+    insert_headless_renderer(&mut app, width, height)?;
+    for _ in 0..frames {
+        app.update();
+    }
+    Some(read_headless_frame(&app.world))
+}
+
+/// Runs `app` without a window until every frame of `replay` has been played back, and returns
+/// the last frame. Used for gameplay smoke tests.
+pub fn run_headless_replay(mut app: App, width: u32, height: u32, replay: InputReplay) -> Option<RgbaImage> {
+    insert_headless_renderer(&mut app, width, height)?;
+    app.insert_resource(replay);
+    while app.world.contains_resource::<InputReplay>() {
+        app.update();
+    }
+    Some(read_headless_frame(&app.world))
+}
+
+/// Inserts a headless device and an offscreen target of the given size for the renderer to draw
+/// to. Returns `None` if no adapter is available.
+pub(crate) fn insert_headless_renderer(app: &mut App, width: u32, height: u32) -> Option<()> {
+    let _ = env_logger::try_init();
+    let (instance, _adapter, device, queue) = pollster::block_on(renderer::initialize_headless())?;
+    let offscreen_target = OffscreenTarget::new(&device, width, height);
+
+    app.insert_resource(instance)
+        .insert_resource(device)
+        .insert_resource(queue)
+        // The renderer sizes its pipeline and depth texture from the surface configuration
+        .insert_resource(offscreen_target.surface_configuration())
+        .insert_resource(offscreen_target);
+    Some(())
+}
+
+fn read_headless_frame(world: &bevy_ecs::world::World) -> RgbaImage {
+    let offscreen_target = world.get_resource::<OffscreenTarget>().unwrap();
+    let device = world.get_resource::<Device>().unwrap();
+    let queue = world.get_resource::<Queue>().unwrap();
+    offscreen_target.read_image(device, queue)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_input::ElementState;
+    use crate::core::recording::{RecordedFrame, RecordedKey};
+    use crate::core::time::Time;
+    use crate::game::GamePlugin;
+    use super::*;
+
+    fn game_app() -> App {
+        let mut app = App::new();
+        app.add_plugin(EnginePlugin).add_plugin(GamePlugin);
+        app
+    }
+
+    fn replayed_frames() -> Vec<RecordedFrame> {
+        let key = |state| RecordedKey { scan_code: 17, key_code: Some(KeyCode::W), state };
+        vec![
+            RecordedFrame { delta_seconds: 0.02, keyboard: vec![key(ElementState::Pressed)] },
+            RecordedFrame { delta_seconds: 0.02, keyboard: Vec::new() },
+            RecordedFrame { delta_seconds: 0.02, keyboard: vec![key(ElementState::Released)] }
+        ]
+    }
+
+    #[test]
+    fn replays_drive_the_app_until_they_end() {
+        let mut app = App::new();
+        app.add_plugin(TimePlugin).add_plugin(InputPlugin).add_plugin(RecordingPlugin);
+        app.insert_resource(InputReplay::new(replayed_frames()));
+
+        let mut held = Vec::new();
+        while app.world.contains_resource::<InputReplay>() {
+            app.update();
+            held.push(app.world.get_resource::<Input<KeyCode>>().unwrap().pressed(KeyCode::W));
+        }
+        assert_eq!(held, [true, true, false]);
+        let time = app.world.get_resource::<Time>().unwrap();
+        assert_eq!(time.frame_count(), 3);
+        assert!((time.elapsed_seconds() - 0.06).abs() < 1e-6);
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn headless_replays_render_the_game() {
+        let replay = InputReplay::new(replayed_frames());
+        let image = run_headless_replay(game_app(), 128, 96, replay).expect("No wgpu adapter available");
+        assert_eq!(image.dimensions(), (128, 96));
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn headless_runs_render_the_game() {
+        let image = run_headless(game_app(), 128, 96, 3).expect("No wgpu adapter available");
+        assert_eq!(image.dimensions(), (128, 96));
+        let background = *image.get_pixel(0, 0);
+        assert!(image.pixels().any(|pixel| *pixel != background), "nothing was drawn");
+    }
+}
@@ -0,0 +1,116 @@
+use bevy_ecs::event::Events;
+use bevy_ecs::prelude::*;
+use bevy_ecs::schedule::{IntoSystemDescriptor, RunOnce, StageLabel};
+use bevy_ecs::system::Resource;
+use crate::app::stage;
+
+/// The label of every `Events::update_system` added by `App::add_event`. Systems in "first" that
+/// read events sent by the event loop run after it, so that they always see the current frame's events.
+pub const EVENT_UPDATE_SYSTEM: &str = "event_update_system";
+
+/// A part of the game or engine that registers its own resources, events and systems with an `App`
+pub trait Plugin {
+    fn build(&self, app: &mut App);
+}
+
+/// The world and the schedule that runs over it every frame, put together by plugins
+pub struct App {
+    pub world: World,
+    pub schedule: Schedule
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl App {
+    /// An app with every stage in `app::stage`, all of them empty
+    pub fn new() -> Self {
+        let mut schedule = Schedule::default();
+        schedule
+            .add_stage(stage::ENGINE_STARTUP, SystemStage::parallel().with_run_criteria(RunOnce::default()))
+            .add_stage(stage::STARTUP, SystemStage::parallel().with_run_criteria(RunOnce::default()))
+            .add_stage(stage::FIRST, SystemStage::parallel())
+            .add_stage(stage::FIXED_UPDATE, SystemStage::parallel())
+            .add_stage(stage::UPDATE, SystemStage::parallel())
+            .add_stage(stage::RENDER, SystemStage::parallel());
+        Self { world: World::new(), schedule }
+    }
+
+    pub fn add_plugin(&mut self, plugin: impl Plugin) -> &mut Self {
+        plugin.build(self);
+        self
+    }
+
+    pub fn insert_resource<R: Resource>(&mut self, resource: R) -> &mut Self {
+        self.world.insert_resource(resource);
+        self
+    }
+
+    pub fn init_resource<R: Resource + FromWorld>(&mut self) -> &mut Self {
+        self.world.init_resource::<R>();
+        self
+    }
+
+    /// Adds the `Events<T>` resource, and swaps its buffers in the "first" stage so that every
+    /// event can be read for two frames
+    pub fn add_event<T: Resource>(&mut self) -> &mut Self {
+        self.world.init_resource::<Events<T>>();
+        self.add_system_to_stage(stage::FIRST, Events::<T>::update_system.label(EVENT_UPDATE_SYSTEM))
+    }
+
+    /// Adds a system to the "update" stage
+    pub fn add_system<Params>(&mut self, system: impl IntoSystemDescriptor<Params>) -> &mut Self {
+        self.add_system_to_stage(stage::UPDATE, system)
+    }
+
+    /// Adds a system that runs once, after the engine has started
+    pub fn add_startup_system<Params>(&mut self, system: impl IntoSystemDescriptor<Params>) -> &mut Self {
+        self.add_system_to_stage(stage::STARTUP, system)
+    }
+
+    pub fn add_system_to_stage<Params>(&mut self, stage_label: impl StageLabel,
+                                       system: impl IntoSystemDescriptor<Params>) -> &mut Self {
+        self.schedule.add_system_to_stage(stage_label, system);
+        self
+    }
+
+    /// Runs every stage once
+    pub fn update(&mut self) {
+        self.schedule.run(&mut self.world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Frames(u32);
+
+    #[derive(Default)]
+    struct Started(u32);
+
+    struct CountingPlugin;
+
+    impl Plugin for CountingPlugin {
+        fn build(&self, app: &mut App) {
+            app.init_resource::<Frames>()
+                .init_resource::<Started>()
+                .add_startup_system(|mut started: ResMut<Started>| started.0 += 1)
+                .add_system(|mut frames: ResMut<Frames>| frames.0 += 1);
+        }
+    }
+
+    #[test]
+    fn plugins_add_resources_and_systems() {
+        let mut app = App::new();
+        app.add_plugin(CountingPlugin);
+        app.update();
+        app.update();
+        assert_eq!(app.world.get_resource::<Frames>().unwrap().0, 2);
+        assert_eq!(app.world.get_resource::<Started>().unwrap().0, 1);
+    }
+}
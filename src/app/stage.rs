@@ -0,0 +1,12 @@
+//! The labels of the stages every `App` runs, in the order they run each frame
+
+/// Runs once, before anything else, to set up the renderer
+pub const ENGINE_STARTUP: &str = "engine_startup";
+/// Runs once after the engine has started, for spawning the game's scene
+pub const STARTUP: &str = "startup";
+/// Advances time and turns the frame's raw input and window events into state
+pub const FIRST: &str = "first";
+/// Runs a whole number of fixed length steps each frame, see `FixedTime`
+pub const FIXED_UPDATE: &str = "fixed_update";
+pub const UPDATE: &str = "update";
+pub const RENDER: &str = "render";
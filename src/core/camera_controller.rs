@@ -0,0 +1,386 @@
+use bevy_ecs::prelude::*;
+use bevy_input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel};
+use glam::{Quat, Vec2, Vec3};
+use crate::app::{App, Plugin};
+use crate::core::input_map;
+use crate::core::input_map::ActionState;
+use crate::core::time::Time;
+use crate::renderer::camera::Camera;
+use crate::renderer::instance::InstancedModel;
+use crate::renderer::model::{Aabb, Model};
+use crate::renderer::Transform;
+
+/// Which controller drives cameras that have both a fly and an orbit controller. The
+/// `toggle_camera_mode` action, Tab by default, switches between them.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CameraControlMode {
+    #[default]
+    Fly,
+    Orbit
+}
+
+/// Marks the models that the orbit controller's `frame_selected` action (F) fits into view. When nothing is
+/// selected, every model is framed.
+#[derive(Component, Default)]
+pub struct Selected;
+
+/// Flies the camera it is attached to. Mouse motion turns it, the `move` action (W/A/S/D by
+/// default) moves it along where it is looking, `move_vertical` (Q/E) moves it straight down and
+/// up, and holding `sprint` (left shift) speeds it up.
+#[derive(Component, Clone, Debug)]
+pub struct FlyCameraController {
+    /// Rotation around the world Y axis in radians. Zero looks down -Z.
+    pub yaw: f32,
+    /// Rotation above the horizon in radians
+    pub pitch: f32,
+    /// Units moved per second
+    pub speed: f32,
+    pub sprint_multiplier: f32,
+    /// Radians turned per pixel of mouse motion
+    pub sensitivity: f32,
+    /// How far up or down the camera can look. Kept short of straight up so that yaw stays well defined.
+    pub max_pitch: f32,
+    pub sprinting: bool
+}
+
+impl Default for FlyCameraController {
+    fn default() -> Self {
+        Self {
+            yaw: 0.0,
+            pitch: 0.0,
+            speed: 10.0,
+            sprint_multiplier: 3.0,
+            sensitivity: 0.002,
+            max_pitch: 89.0_f32.to_radians(),
+            sprinting: false
+        }
+    }
+}
+
+impl FlyCameraController {
+    /// A controller that starts out facing along `forward`, such as a camera's current forward vector
+    pub fn looking_along(forward: Vec3) -> Self {
+        let forward = forward.normalize();
+        let controller = Self::default();
+        Self {
+            yaw: (-forward.x).atan2(-forward.z),
+            pitch: forward.y.clamp(-1.0, 1.0).asin().clamp(-controller.max_pitch, controller.max_pitch),
+            ..controller
+        }
+    }
+
+    pub fn orientation(&self) -> Quat {
+        Quat::from_rotation_y(self.yaw) * Quat::from_rotation_x(self.pitch)
+    }
+
+    /// Turns by a mouse movement in pixels, where positive x is to the right and positive y is down
+    pub fn rotate(&mut self, mouse_delta: Vec2) {
+        self.yaw -= mouse_delta.x * self.sensitivity;
+        self.pitch = (self.pitch - mouse_delta.y * self.sensitivity).clamp(-self.max_pitch, self.max_pitch);
+    }
+
+    /// How far to move for `input`, where x is right, y is up and z is forward, each from -1 to 1
+    pub fn translation(&self, input: Vec3, delta_seconds: f32) -> Vec3 {
+        let orientation = self.orientation();
+        let direction = orientation * Vec3::X * input.x + Vec3::Y * input.y + orientation * -Vec3::Z * input.z;
+        let speed = if self.sprinting { self.speed * self.sprint_multiplier } else { self.speed };
+        direction.normalize_or_zero() * speed * delta_seconds
+    }
+}
+
+/// Orbits the camera it is attached to around a focus point, for inspecting models. Dragging while
+/// `orbit_rotate` is held (the left mouse button by default) rotates, dragging while `orbit_pan`
+/// is held (the middle button) pans, and the scroll wheel moves closer or further away.
+#[derive(Component, Clone, Debug)]
+pub struct OrbitCameraController {
+    pub focus: Vec3,
+    pub distance: f32,
+    /// Rotation around the world Y axis in radians. Zero looks down -Z.
+    pub yaw: f32,
+    /// Rotation above the horizon in radians
+    pub pitch: f32,
+    /// Radians turned per pixel of mouse motion
+    pub rotate_sensitivity: f32,
+    /// The fraction of `distance` panned per pixel of mouse motion
+    pub pan_sensitivity: f32,
+    /// The fraction of `distance` moved per line scrolled
+    pub zoom_sensitivity: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub max_pitch: f32
+}
+
+impl Default for OrbitCameraController {
+    fn default() -> Self {
+        Self {
+            focus: Vec3::ZERO,
+            distance: 5.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            rotate_sensitivity: 0.005,
+            pan_sensitivity: 0.001,
+            zoom_sensitivity: 0.1,
+            min_distance: 0.1,
+            max_distance: 1000.0,
+            max_pitch: 89.0_f32.to_radians()
+        }
+    }
+}
+
+impl OrbitCameraController {
+    /// Scroll events in pixels are converted to lines at this rate
+    const PIXELS_PER_LINE: f32 = 20.0;
+
+    /// A controller orbiting `focus` from where the camera currently is
+    pub fn around(focus: Vec3, position: Vec3) -> Self {
+        let mut controller = Self { focus, ..Self::default() };
+        let offset = focus - position;
+        controller.distance = offset.length().max(controller.min_distance);
+        controller.look_along(offset);
+        controller
+    }
+
+    /// Keeps the distance but moves the focus in front of a camera at `position` facing along
+    /// `forward`, so that switching to this controller doesn't move the camera
+    pub fn sync_to(&mut self, position: Vec3, forward: Vec3) {
+        self.look_along(forward);
+        self.focus = position + forward.normalize() * self.distance;
+    }
+
+    fn look_along(&mut self, forward: Vec3) {
+        let forward = forward.normalize();
+        self.yaw = (-forward.x).atan2(-forward.z);
+        self.pitch = forward.y.clamp(-1.0, 1.0).asin().clamp(-self.max_pitch, self.max_pitch);
+    }
+
+    pub fn orientation(&self) -> Quat {
+        Quat::from_rotation_y(self.yaw) * Quat::from_rotation_x(self.pitch)
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.focus + self.orientation() * Vec3::Z * self.distance
+    }
+
+    /// Orbits by a mouse movement in pixels, where positive x is to the right and positive y is down
+    pub fn rotate(&mut self, mouse_delta: Vec2) {
+        self.yaw -= mouse_delta.x * self.rotate_sensitivity;
+        self.pitch = (self.pitch - mouse_delta.y * self.rotate_sensitivity).clamp(-self.max_pitch, self.max_pitch);
+    }
+
+    /// Moves the focus so that the scene follows the mouse
+    pub fn pan(&mut self, mouse_delta: Vec2) {
+        let orientation = self.orientation();
+        let scale = self.pan_sensitivity * self.distance;
+        self.focus += (orientation * -Vec3::X * mouse_delta.x + orientation * Vec3::Y * mouse_delta.y) * scale;
+    }
+
+    /// Moves towards the focus for positive `lines` and away for negative ones
+    pub fn dolly(&mut self, lines: f32) {
+        self.distance = (self.distance * (1.0 - self.zoom_sensitivity).powf(lines))
+            .clamp(self.min_distance, self.max_distance);
+    }
+
+    /// Focuses on the center of `bounds` from far enough away that all of it is in view
+    pub fn frame(&mut self, bounds: Aabb, fov_y: f32, aspect: f32) {
+        let fov_x = 2.0 * ((fov_y * 0.5).tan() * aspect).atan();
+        let radius = bounds.half_extents().length().max(self.min_distance);
+        self.focus = bounds.center();
+        self.distance = (radius / (fov_y.min(fov_x) * 0.5).sin()).clamp(self.min_distance, self.max_distance);
+    }
+}
+
+/// Adds the fly and orbit camera controls, switched between with `TOGGLE_CAMERA_MODE`
+pub struct CameraControllerPlugin;
+
+impl Plugin for CameraControllerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CameraControlMode>()
+            .add_system(switch_camera_control_mode.label("switch_camera_control_mode"))
+            .add_system(fly_camera_control.after("switch_camera_control_mode"))
+            .add_system(orbit_camera_control.after("switch_camera_control_mode"))
+            .add_system(frame_selected.after("switch_camera_control_mode"));
+    }
+}
+
+pub(crate) fn fly_camera_control(mode: Res<CameraControlMode>, mut cameras: Query<(&mut Camera, &mut FlyCameraController)>,
+                                 actions: Res<ActionState>, mut mouse_motion_events: EventReader<MouseMotion>,
+                                 time: Res<Time>) {
+    let mouse_delta: Vec2 = mouse_motion_events.iter().map(|motion| &motion.delta).sum();
+    if *mode != CameraControlMode::Fly {
+        return;
+    }
+
+    let movement = actions.vector(input_map::MOVE);
+    let input = Vec3::new(movement.x, actions.axis(input_map::MOVE_VERTICAL), movement.y);
+    for (mut camera, mut controller) in cameras.iter_mut() {
+        controller.sprinting = actions.pressed(input_map::SPRINT);
+        controller.rotate(mouse_delta);
+        camera.orientation = controller.orientation();
+        // The fly camera is a debug tool, so it keeps moving while game time is paused or slowed down
+        camera.position += controller.translation(input, time.unscaled_delta_seconds());
+    }
+}
+
+pub(crate) fn orbit_camera_control(mode: Res<CameraControlMode>,
+                                   mut cameras: Query<(&mut Camera, &mut OrbitCameraController)>,
+                                   actions: Res<ActionState>,
+                                   mut mouse_motion_events: EventReader<MouseMotion>,
+                                   mut mouse_wheel_events: EventReader<MouseWheel>) {
+    let mouse_delta: Vec2 = mouse_motion_events.iter().map(|motion| &motion.delta).sum();
+    let scrolled_lines: f32 = mouse_wheel_events.iter().map(|wheel| match wheel.unit {
+        MouseScrollUnit::Line => wheel.y,
+        MouseScrollUnit::Pixel => wheel.y / OrbitCameraController::PIXELS_PER_LINE
+    }).sum();
+    if *mode != CameraControlMode::Orbit {
+        return;
+    }
+
+    for (mut camera, mut controller) in cameras.iter_mut() {
+        if actions.pressed(input_map::ORBIT_ROTATE) {
+            controller.rotate(mouse_delta);
+        }
+        if actions.pressed(input_map::ORBIT_PAN) {
+            controller.pan(mouse_delta);
+        }
+        controller.dolly(scrolled_lines);
+        camera.orientation = controller.orientation();
+        camera.position = controller.position();
+    }
+}
+
+/// Switches between the fly and orbit controllers on `toggle_camera_mode`. The controller being switched to picks
+/// up from the camera's current pose.
+pub(crate) fn switch_camera_control_mode(mut mode: ResMut<CameraControlMode>, actions: Res<ActionState>,
+                                         mut cameras: Query<(&Camera, Option<&mut FlyCameraController>,
+                                                             Option<&mut OrbitCameraController>)>) {
+    if !actions.just_pressed(input_map::TOGGLE_CAMERA_MODE) {
+        return;
+    }
+
+    *mode = match *mode {
+        CameraControlMode::Fly => CameraControlMode::Orbit,
+        CameraControlMode::Orbit => CameraControlMode::Fly
+    };
+    for (camera, fly_controller, orbit_controller) in cameras.iter_mut() {
+        match (*mode, fly_controller, orbit_controller) {
+            (CameraControlMode::Fly, Some(mut fly_controller), _) => {
+                let FlyCameraController { yaw, pitch, .. } = FlyCameraController::looking_along(camera.forward());
+                fly_controller.yaw = yaw;
+                fly_controller.pitch = pitch;
+            },
+            (CameraControlMode::Orbit, _, Some(mut orbit_controller)) =>
+                orbit_controller.sync_to(camera.position, camera.forward()),
+            _ => {}
+        }
+    }
+}
+
+/// Fits the selected models, or every model if none are selected, into the orbit camera's view on
+/// `frame_selected`. Instanced models count as models of their own.
+pub(crate) fn frame_selected(mode: Res<CameraControlMode>, actions: Res<ActionState>,
+                             models: Query<(&Model, &Transform, Option<&Selected>)>,
+                             instanced_models: Query<(&InstancedModel, &Transform, Option<&Selected>)>,
+                             mut cameras: Query<(&Camera, &mut OrbitCameraController)>) {
+    if !actions.just_pressed(input_map::FRAME_SELECTED) || *mode != CameraControlMode::Orbit {
+        return;
+    }
+
+    let all_models = || models.iter()
+        .map(|(model, transform, selected)| (model, transform, selected.is_some()))
+        .chain(instanced_models.iter()
+            .map(|(instanced_model, transform, selected)| (instanced_model.model.as_ref(), transform, selected.is_some())));
+    let any_selected = all_models().any(|(_, _, selected)| selected);
+    let bounds = all_models()
+        .filter(|(_, _, selected)| *selected || !any_selected)
+        .filter_map(|(model, transform, _)| model.bounds().map(|bounds| bounds.transformed(transform.matrix)))
+        .reduce(Aabb::union);
+    if let Some(bounds) = bounds {
+        for (camera, mut controller) in cameras.iter_mut() {
+            controller.frame(bounds, camera.fov_y, camera.aspect);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looking_along_a_direction_recovers_it() {
+        let forward = Vec3::new(1.0, 0.5, -2.0).normalize();
+        let controller = FlyCameraController::looking_along(forward);
+        let recovered = controller.orientation() * -Vec3::Z;
+        assert!(recovered.abs_diff_eq(forward, 1e-5), "forward was {:?}", recovered);
+    }
+
+    #[test]
+    fn pitch_is_clamped() {
+        let mut controller = FlyCameraController::default();
+        controller.rotate(Vec2::new(0.0, -100_000.0));
+        assert_eq!(controller.pitch, controller.max_pitch);
+        controller.rotate(Vec2::new(0.0, 100_000.0));
+        assert_eq!(controller.pitch, -controller.max_pitch);
+    }
+
+    #[test]
+    fn moving_right_turns_right() {
+        let mut controller = FlyCameraController::default();
+        controller.rotate(Vec2::new(100.0, 0.0));
+        let forward = controller.orientation() * -Vec3::Z;
+        assert!(forward.x > 0.0, "forward was {:?}", forward);
+    }
+
+    #[test]
+    fn sprinting_multiplies_speed() {
+        let mut controller = FlyCameraController::default();
+        let walk = controller.translation(Vec3::Z, 1.0);
+        assert!(walk.abs_diff_eq(-Vec3::Z * controller.speed, 1e-5), "walked {:?}", walk);
+
+        controller.sprinting = true;
+        let sprint = controller.translation(Vec3::Z, 1.0);
+        assert!((sprint.length() - controller.speed * controller.sprint_multiplier).abs() < 1e-4);
+    }
+
+    #[test]
+    fn orbit_keeps_its_distance_from_the_focus() {
+        let focus = Vec3::new(1.0, 2.0, 3.0);
+        let mut controller = OrbitCameraController::around(focus, Vec3::new(1.0, 2.0, 8.0));
+        assert!(controller.position().abs_diff_eq(Vec3::new(1.0, 2.0, 8.0), 1e-5), "position was {:?}", controller.position());
+
+        controller.rotate(Vec2::new(300.0, -120.0));
+        assert!((controller.position().distance(focus) - 5.0).abs() < 1e-4);
+        let forward = controller.orientation() * -Vec3::Z;
+        assert!(forward.abs_diff_eq((focus - controller.position()).normalize(), 1e-5));
+    }
+
+    #[test]
+    fn dolly_is_clamped() {
+        let mut controller = OrbitCameraController::default();
+        controller.dolly(1000.0);
+        assert_eq!(controller.distance, controller.min_distance);
+        controller.dolly(-1000.0);
+        assert_eq!(controller.distance, controller.max_distance);
+    }
+
+    #[test]
+    fn framing_fits_the_bounds_in_view() {
+        let bounds = Aabb { min: Vec3::new(9.0, -1.0, -1.0), max: Vec3::new(11.0, 1.0, 1.0) };
+        let fov_y = 45.0_f32.to_radians();
+        let mut controller = OrbitCameraController::default();
+        controller.frame(bounds, fov_y, 16.0 / 9.0);
+
+        assert_eq!(controller.focus, Vec3::new(10.0, 0.0, 0.0));
+        // The bounding sphere must fit inside the vertical field of view
+        let radius = bounds.half_extents().length();
+        assert!((radius / controller.distance).asin() <= fov_y * 0.5 + 1e-5);
+    }
+
+    #[test]
+    fn syncing_the_orbit_keeps_the_camera_in_place() {
+        let position = Vec3::new(0.0, 3.0, 6.0);
+        let forward = Vec3::new(0.2, -0.4, -1.0).normalize();
+        let mut controller = OrbitCameraController::default();
+        controller.sync_to(position, forward);
+        assert!(controller.position().abs_diff_eq(position, 1e-4), "position was {:?}", controller.position());
+    }
+}
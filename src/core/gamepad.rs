@@ -0,0 +1,299 @@
+//! Gamepads feed bevy_input's gamepad events: `GamepadEventRaw`s come in from the backend, and
+//! bevy_input's `gamepad_event_system` filters them through `GamepadSettings` into the
+//! `Input<GamepadButton>`, `Axis<GamepadAxis>` and `Axis<GamepadButton>` resources. The backend is
+//! gilrs, compiled in with the `gamepad` feature. Without it no gamepad is ever connected, but
+//! everything reading gamepad state keeps working.
+
+use std::time::Duration;
+use bevy_ecs::prelude::*;
+use bevy_input::Axis;
+use bevy_input::Input;
+use bevy_input::gamepad::{AxisSettings, Gamepad, GamepadAxis, GamepadButton, GamepadEvent, GamepadEventRaw, GamepadSettings, Gamepads};
+use crate::app::{stage, App, Plugin};
+
+/// How far a stick has to move before it registers, as a fraction of its full range. Worn sticks
+/// rarely rest exactly at the center.
+pub const DEFAULT_DEAD_ZONE: f32 = 0.15;
+
+/// Asks a gamepad to vibrate. Ignored by gamepads without force feedback, and when gamepad support
+/// isn't compiled in.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GamepadRumble {
+    pub gamepad: Gamepad,
+    /// Strength of the low frequency motor, from 0 to 1
+    pub strong: f32,
+    /// Strength of the high frequency motor, from 0 to 1
+    pub weak: f32,
+    pub duration: Duration
+}
+
+/// Axis settings that read 0 while the axis is within `dead_zone` of its center
+pub fn dead_zone_axis_settings(dead_zone: f32) -> AxisSettings {
+    AxisSettings {
+        positive_low: dead_zone,
+        negative_low: -dead_zone,
+        ..AxisSettings::default()
+    }
+}
+
+/// Settings applying `dead_zone` to every axis of every gamepad. Individual axes can be given their
+/// own through `GamepadSettings::axis_settings`.
+pub fn gamepad_settings(dead_zone: f32) -> GamepadSettings {
+    GamepadSettings {
+        default_axis_settings: dead_zone_axis_settings(dead_zone),
+        ..GamepadSettings::default()
+    }
+}
+
+/// Adds the gamepad state and events, and the systems turning gamepad input into state in the
+/// "first" stage. The gilrs backend feeds them when it is compiled in.
+pub struct GamepadPlugin;
+
+impl Plugin for GamepadPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Gamepads>()
+            .init_resource::<Input<GamepadButton>>()
+            .init_resource::<Axis<GamepadAxis>>()
+            .init_resource::<Axis<GamepadButton>>()
+            .insert_resource(gamepad_settings(DEFAULT_DEAD_ZONE))
+            .add_event::<GamepadEventRaw>()
+            .add_event::<GamepadEvent>()
+            .add_event::<GamepadRumble>()
+            .add_system_to_stage(stage::FIRST, bevy_input::gamepad::gamepad_event_system.label("gamepad_event_system"))
+            .add_system_to_stage(stage::FIRST,
+                                 bevy_input::gamepad::gamepad_connection_system.after("gamepad_event_system"));
+        #[cfg(feature = "gamepad")]
+        {
+            gilrs_backend::insert_gilrs(&mut app.world);
+            app.add_system_to_stage(stage::FIRST, gilrs_backend::gilrs_event_system.before("gamepad_event_system"))
+                .add_system_to_stage(stage::FIRST, gilrs_backend::gilrs_rumble_system);
+        }
+    }
+}
+
+#[cfg(feature = "gamepad")]
+mod gilrs_backend {
+    use std::time::Instant;
+    use bevy_ecs::prelude::*;
+    use bevy_input::gamepad::{Gamepad, GamepadAxisType, GamepadButtonType, GamepadEventRaw, GamepadEventType};
+    use gilrs::{Axis, Button, EventType, Gilrs};
+    use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Repeat, Replay, Ticks};
+    use crate::core::recording::InputReplay;
+    use super::GamepadRumble;
+
+    /// Gilrs and the rumble effects it is playing, which stop as soon as they are dropped
+    pub(super) struct GilrsBackend {
+        gilrs: Gilrs,
+        rumbles: Vec<(Instant, Effect)>
+    }
+
+    /// Gilrs can fail to start, for example without access to udev. The game then runs without gamepads.
+    pub(super) fn insert_gilrs(world: &mut World) {
+        match Gilrs::new() {
+            Ok(gilrs) => world.insert_non_send_resource(GilrsBackend { gilrs, rumbles: Vec::new() }),
+            Err(error) => log::error!("Unable to start gamepad support: {}", error)
+        }
+    }
+
+    /// Forwards gilrs events, except during a replay, when they are drained and dropped so that a
+    /// gamepad in use can't change the replayed input or flood in once the replay ends
+    pub(super) fn gilrs_event_system(backend: Option<NonSendMut<GilrsBackend>>, replay: Option<Res<InputReplay>>,
+                                     mut events: EventWriter<GamepadEventRaw>) {
+        let mut backend = match backend {
+            Some(backend) => backend,
+            None => return
+        };
+        if replay.is_some() {
+            while backend.gilrs.next_event().is_some() {}
+            return;
+        }
+        while let Some(event) = backend.gilrs.next_event() {
+            let gamepad = Gamepad(event.id.into());
+            if let Some(event_type) = convert_gilrs_event_type(event.event) {
+                events.send(GamepadEventRaw(gamepad, event_type));
+            }
+        }
+    }
+
+    pub(super) fn gilrs_rumble_system(backend: Option<NonSendMut<GilrsBackend>>,
+                                      mut rumble_requests: EventReader<GamepadRumble>) {
+        let mut backend = match backend {
+            Some(backend) => backend,
+            None => return
+        };
+        let now = Instant::now();
+        backend.rumbles.retain(|(ends_at, _)| *ends_at > now);
+
+        for request in rumble_requests.iter() {
+            match play_rumble(&mut backend.gilrs, request) {
+                Ok(Some(effect)) => backend.rumbles.push((now + request.duration, effect)),
+                Ok(None) => {},
+                Err(error) => log::warn!("Unable to rumble {:?}: {}", request.gamepad, error)
+            }
+        }
+    }
+
+    /// Starts the effect, or returns `None` if the gamepad is gone or can't rumble
+    fn play_rumble(gilrs: &mut Gilrs, request: &GamepadRumble) -> Result<Option<Effect>, gilrs::ff::Error> {
+        let id = gilrs.gamepads()
+            .find(|(id, gamepad)| Into::<usize>::into(*id) == request.gamepad.0 && gamepad.is_ff_supported())
+            .map(|(id, _)| id);
+        let id = match id {
+            Some(id) => id,
+            None => return Ok(None)
+        };
+
+        let duration = Ticks::from_ms(request.duration.as_millis().min(u32::MAX as u128) as u32);
+        let scheduling = Replay { play_for: duration, ..Replay::default() };
+        let magnitude = |strength: f32| (strength.clamp(0.0, 1.0) * u16::MAX as f32) as u16;
+        let effect = EffectBuilder::new()
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Strong { magnitude: magnitude(request.strong) },
+                scheduling,
+                ..BaseEffect::default()
+            })
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Weak { magnitude: magnitude(request.weak) },
+                scheduling,
+                ..BaseEffect::default()
+            })
+            .repeat(Repeat::For(duration))
+            .gamepads(&[id])
+            .finish(gilrs)?;
+        effect.play()?;
+        Ok(Some(effect))
+    }
+
+    /// Pressed and released events are left out, since the changed events carry the same
+    /// information along with how far the button is pressed
+    fn convert_gilrs_event_type(event_type: EventType) -> Option<GamepadEventType> {
+        match event_type {
+            EventType::Connected => Some(GamepadEventType::Connected),
+            EventType::Disconnected => Some(GamepadEventType::Disconnected),
+            EventType::ButtonChanged(button, value, _) =>
+                convert_gilrs_button(button).map(|button| GamepadEventType::ButtonChanged(button, value)),
+            EventType::AxisChanged(axis, value, _) =>
+                convert_gilrs_axis(axis).map(|axis| GamepadEventType::AxisChanged(axis, value)),
+            _ => None
+        }
+    }
+
+    pub(super) fn convert_gilrs_button(button: Button) -> Option<GamepadButtonType> {
+        match button {
+            Button::South => Some(GamepadButtonType::South),
+            Button::East => Some(GamepadButtonType::East),
+            Button::North => Some(GamepadButtonType::North),
+            Button::West => Some(GamepadButtonType::West),
+            Button::C => Some(GamepadButtonType::C),
+            Button::Z => Some(GamepadButtonType::Z),
+            Button::LeftTrigger => Some(GamepadButtonType::LeftTrigger),
+            Button::LeftTrigger2 => Some(GamepadButtonType::LeftTrigger2),
+            Button::RightTrigger => Some(GamepadButtonType::RightTrigger),
+            Button::RightTrigger2 => Some(GamepadButtonType::RightTrigger2),
+            Button::Select => Some(GamepadButtonType::Select),
+            Button::Start => Some(GamepadButtonType::Start),
+            Button::Mode => Some(GamepadButtonType::Mode),
+            Button::LeftThumb => Some(GamepadButtonType::LeftThumb),
+            Button::RightThumb => Some(GamepadButtonType::RightThumb),
+            Button::DPadUp => Some(GamepadButtonType::DPadUp),
+            Button::DPadDown => Some(GamepadButtonType::DPadDown),
+            Button::DPadLeft => Some(GamepadButtonType::DPadLeft),
+            Button::DPadRight => Some(GamepadButtonType::DPadRight),
+            Button::Unknown => None
+        }
+    }
+
+    pub(super) fn convert_gilrs_axis(axis: Axis) -> Option<GamepadAxisType> {
+        match axis {
+            Axis::LeftStickX => Some(GamepadAxisType::LeftStickX),
+            Axis::LeftStickY => Some(GamepadAxisType::LeftStickY),
+            Axis::LeftZ => Some(GamepadAxisType::LeftZ),
+            Axis::RightStickX => Some(GamepadAxisType::RightStickX),
+            Axis::RightStickY => Some(GamepadAxisType::RightStickY),
+            Axis::RightZ => Some(GamepadAxisType::RightZ),
+            Axis::DPadX => Some(GamepadAxisType::DPadX),
+            Axis::DPadY => Some(GamepadAxisType::DPadY),
+            Axis::Unknown => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::event::Events;
+    use bevy_input::gamepad::{GamepadAxisType, GamepadButtonType, GamepadEventType};
+    use super::*;
+
+    const GAMEPAD: Gamepad = Gamepad(0);
+
+    fn gamepad_app() -> App {
+        let mut app = App::new();
+        app.add_plugin(GamepadPlugin);
+        app
+    }
+
+    /// Sends events the way the gilrs backend would and runs a frame
+    fn send_raw(app: &mut App, events: &[GamepadEventType]) {
+        let mut raw_events = app.world.get_resource_mut::<Events<GamepadEventRaw>>().unwrap();
+        for event in events {
+            raw_events.send(GamepadEventRaw(GAMEPAD, event.clone()));
+        }
+        app.update();
+    }
+
+    #[test]
+    fn connections_are_tracked() {
+        let mut app = gamepad_app();
+        send_raw(&mut app, &[GamepadEventType::Connected]);
+        assert!(app.world.get_resource::<Gamepads>().unwrap().contains(&GAMEPAD));
+
+        send_raw(&mut app, &[GamepadEventType::Disconnected]);
+        assert!(!app.world.get_resource::<Gamepads>().unwrap().contains(&GAMEPAD));
+    }
+
+    #[test]
+    fn sticks_rest_inside_the_dead_zone() {
+        let mut app = gamepad_app();
+        let stick = GamepadAxis(GAMEPAD, GamepadAxisType::LeftStickX);
+        send_raw(&mut app, &[
+            GamepadEventType::Connected,
+            GamepadEventType::AxisChanged(GamepadAxisType::LeftStickX, DEFAULT_DEAD_ZONE * 0.5)
+        ]);
+        assert_eq!(app.world.get_resource::<Axis<GamepadAxis>>().unwrap().get(stick), Some(0.0));
+
+        send_raw(&mut app, &[GamepadEventType::AxisChanged(GamepadAxisType::LeftStickX, -0.5)]);
+        assert_eq!(app.world.get_resource::<Axis<GamepadAxis>>().unwrap().get(stick), Some(-0.5));
+    }
+
+    #[test]
+    fn buttons_are_pressed_for_one_frame_then_held() {
+        let mut app = gamepad_app();
+        let button = GamepadButton(GAMEPAD, GamepadButtonType::South);
+        send_raw(&mut app, &[
+            GamepadEventType::Connected,
+            GamepadEventType::ButtonChanged(GamepadButtonType::South, 1.0)
+        ]);
+        let buttons = app.world.get_resource::<Input<GamepadButton>>().unwrap();
+        assert!(buttons.just_pressed(button) && buttons.pressed(button));
+
+        send_raw(&mut app, &[]);
+        let buttons = app.world.get_resource::<Input<GamepadButton>>().unwrap();
+        assert!(!buttons.just_pressed(button) && buttons.pressed(button));
+
+        send_raw(&mut app, &[GamepadEventType::ButtonChanged(GamepadButtonType::South, 0.0)]);
+        let buttons = app.world.get_resource::<Input<GamepadButton>>().unwrap();
+        assert!(buttons.just_released(button) && !buttons.pressed(button));
+    }
+
+    #[cfg(feature = "gamepad")]
+    #[test]
+    fn only_unknown_gilrs_inputs_are_dropped() {
+        use gilrs::{Axis, Button};
+        use super::gilrs_backend::{convert_gilrs_axis, convert_gilrs_button};
+
+        assert_eq!(convert_gilrs_button(Button::DPadLeft), Some(GamepadButtonType::DPadLeft));
+        assert_eq!(convert_gilrs_button(Button::Unknown), None);
+        assert_eq!(convert_gilrs_axis(Axis::RightStickY), Some(GamepadAxisType::RightStickY));
+        assert_eq!(convert_gilrs_axis(Axis::Unknown), None);
+    }
+}
@@ -0,0 +1,256 @@
+//! Golden image tests render a scene headless and compare the result against a reference PNG in
+//! `tests/golden`. They need a wgpu adapter, so they are ignored by default and run with
+//! `cargo test -- --ignored`. A missing reference fails the test; setting `UPDATE_GOLDEN=1` writes
+//! every reference from the current output, for new scenes and after an intended change to the
+//! renderer. On a mismatch the rendered frame and a diff image are written to `target/golden`.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use bevy_ecs::schedule::IntoSystemDescriptor;
+use image::{Rgba, RgbaImage};
+use crate::app::{self, App};
+use crate::core::time::{Clock, Time, TimePlugin};
+use crate::renderer::RendererPlugin;
+
+/// The time step every frame advances by, so that anything animated ends up in the same place
+pub const FIXED_DELTA: Duration = Duration::from_micros(16_667);
+
+/// How far a rendered frame may drift from its reference, to absorb rounding differences between
+/// GPUs and drivers
+#[derive(Copy, Clone, Debug)]
+pub struct Tolerance {
+    /// The largest difference in any channel for a pixel to still count as matching
+    pub channel: u8,
+    /// The fraction of pixels, from 0 to 1, that may differ by more than `channel`
+    pub differing_pixels: f32
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self { channel: 8, differing_pixels: 0.001 }
+    }
+}
+
+pub struct GoldenScene {
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub frames: usize,
+    pub tolerance: Tolerance
+}
+
+impl GoldenScene {
+    pub fn new(name: &'static str) -> Self {
+        Self { name, width: 256, height: 256, frames: 1, tolerance: Tolerance::default() }
+    }
+
+    /// Renders the scene spawned by `setup` and compares the last frame to the reference image.
+    /// `setup` runs once after the renderer has started, like `game::start`, so the camera already
+    /// exists. Panics if no adapter is available.
+    pub fn assert_matches<Params>(&self, setup: impl IntoSystemDescriptor<Params>) {
+        let image = self.render(setup).expect("No wgpu adapter available");
+        assert_matches_reference(self.name, &image, self.tolerance);
+    }
+
+    fn render<Params>(&self, setup: impl IntoSystemDescriptor<Params>) -> Option<RgbaImage> {
+        let mut app = App::new();
+        app.add_plugin(TimePlugin)
+            .add_plugin(RendererPlugin)
+            .insert_resource(Time::with_clock(Clock::Manual(FIXED_DELTA)))
+            .add_startup_system(setup);
+        app::run_headless(app, self.width, self.height, self.frames)
+    }
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(format!("{}.png", name))
+}
+
+fn output_path(name: &str, suffix: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("golden").join(format!("{}.{}.png", name, suffix))
+}
+
+fn save(image: &RgbaImage, path: &Path) {
+    std::fs::create_dir_all(path.parent().unwrap()).expect("Unable to create golden image directory");
+    image.save(path).unwrap_or_else(|error| panic!("Unable to write {}: {}", path.display(), error));
+}
+
+pub fn assert_matches_reference(name: &str, image: &RgbaImage, tolerance: Tolerance) {
+    let reference_path = reference_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        save(image, &reference_path);
+        eprintln!("Wrote golden image {}", reference_path.display());
+        return;
+    }
+    if !reference_path.exists() {
+        let actual_path = output_path(name, "actual");
+        save(image, &actual_path);
+        panic!("Golden image {} has no reference {}. Check the rendered frame in {} and run with \
+                UPDATE_GOLDEN=1 to accept it", name, reference_path.display(), actual_path.display());
+    }
+
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|error| panic!("Unable to read {}: {}", reference_path.display(), error))
+        .to_rgba8();
+    if let Err(mismatch) = compare(&reference, image, tolerance) {
+        let actual_path = output_path(name, "actual");
+        save(image, &actual_path);
+        if let Some(diff) = &mismatch.diff {
+            save(diff, &output_path(name, "diff"));
+        }
+        panic!("Golden image {} does not match: {}. The rendered frame was written to {}",
+               name, mismatch.reason, actual_path.display());
+    }
+}
+
+#[derive(Debug)]
+pub struct Mismatch {
+    pub reason: String,
+    /// The rendered frame dimmed to grey with every differing pixel marked in red. `None` if the
+    /// sizes don't match.
+    pub diff: Option<RgbaImage>
+}
+
+pub fn compare(reference: &RgbaImage, actual: &RgbaImage, tolerance: Tolerance) -> Result<(), Mismatch> {
+    if reference.dimensions() != actual.dimensions() {
+        return Err(Mismatch {
+            reason: format!("expected a {:?} image but got {:?}", reference.dimensions(), actual.dimensions()),
+            diff: None
+        });
+    }
+
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut differing_pixels = 0;
+    for ((expected, actual), diff_pixel) in reference.pixels().zip(actual.pixels()).zip(diff.pixels_mut()) {
+        let difference = expected.0.iter().zip(actual.0).map(|(e, a)| e.abs_diff(a)).max().unwrap_or(0);
+        *diff_pixel = if difference > tolerance.channel {
+            differing_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let luminance = ((actual[0] as u32 + actual[1] as u32 + actual[2] as u32) / 12) as u8;
+            Rgba([luminance, luminance, luminance, 255])
+        };
+    }
+
+    let pixel_count = (actual.width() * actual.height()).max(1);
+    let differing_fraction = differing_pixels as f32 / pixel_count as f32;
+    if differing_fraction > tolerance.differing_pixels {
+        return Err(Mismatch {
+            reason: format!("{} of {} pixels differ by more than {}", differing_pixels, pixel_count, tolerance.channel),
+            diff: Some(diff)
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use bevy_ecs::prelude::*;
+    use glam::{Mat4, Quat, Vec3};
+    use wgpu::{Device, Queue};
+    use crate::renderer::camera::Camera;
+    use crate::renderer::instance::InstancedModel;
+    use crate::renderer::light::{DirectionalLight, PointLight, SpotLight};
+    use crate::renderer::model::{Model, ModelLoadType};
+    use crate::renderer::pipeline::RenderPipeline;
+    use crate::renderer::Transform;
+    use super::*;
+
+    #[test]
+    fn small_differences_are_tolerated() {
+        let reference = RgbaImage::from_pixel(10, 10, Rgba([100, 100, 100, 255]));
+        let mut actual = RgbaImage::from_pixel(10, 10, Rgba([104, 97, 100, 255]));
+        assert!(compare(&reference, &actual, Tolerance::default()).is_ok());
+
+        actual.put_pixel(3, 4, Rgba([0, 0, 0, 255]));
+        let tolerance = Tolerance { channel: 8, differing_pixels: 0.0 };
+        let mismatch = compare(&reference, &actual, tolerance).unwrap_err();
+        let diff = mismatch.diff.unwrap();
+        assert_eq!(*diff.get_pixel(3, 4), Rgba([255, 0, 0, 255]));
+        assert_ne!(*diff.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn images_of_different_sizes_never_match() {
+        let reference = RgbaImage::new(4, 4);
+        let actual = RgbaImage::new(4, 2);
+        let mismatch = compare(&reference, &actual, Tolerance::default()).unwrap_err();
+        assert!(mismatch.diff.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "has no reference")]
+    fn missing_references_fail() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+        assert_matches_reference("missing_reference", &image, Tolerance::default());
+    }
+
+    fn lit_cube(mut commands: Commands, device: Res<Device>, queue: Res<Queue>,
+                render_pipeline: Res<RenderPipeline>, mut cameras: Query<&mut Camera>) {
+        let mut camera = cameras.single_mut();
+        camera.position = Vec3::new(2.0, 2.0, 4.0);
+        camera.look_at(Vec3::ZERO, Vec3::Y);
+
+        let cube = Model::load_model(ModelLoadType::OBJ, "cube.obj", &device, &queue,
+                                     &render_pipeline.material_bind_group_layout).unwrap();
+        commands.spawn()
+            .insert(cube)
+            .insert(Transform::from_rotation_translation(Quat::from_rotation_y(0.5), 0.0, 0.0, 0.0));
+        commands.spawn().insert(DirectionalLight {
+            direction: Vec3::new(-1.0, -2.0, -1.5),
+            color: Vec3::ONE,
+            intensity: 1.0
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn lit_cube_matches_golden_image() {
+        GoldenScene { frames: 3, ..GoldenScene::new("lit_cube") }.assert_matches(lit_cube);
+    }
+
+    /// Three instances of one cube lit by a point light and a spot light. The middle cube is
+    /// stretched so that its normals only come out right through the normal matrix.
+    fn instanced_cubes(mut commands: Commands, device: Res<Device>, queue: Res<Queue>,
+                       render_pipeline: Res<RenderPipeline>, mut cameras: Query<&mut Camera>) {
+        let mut camera = cameras.single_mut();
+        camera.position = Vec3::new(0.0, 3.0, 7.0);
+        camera.look_at(Vec3::ZERO, Vec3::Y);
+
+        let cube = InstancedModel::new(Arc::new(
+            Model::load_model(ModelLoadType::OBJ, "cube.obj", &device, &queue,
+                              &render_pipeline.material_bind_group_layout).unwrap()
+        ));
+        let transforms = [
+            Mat4::from_rotation_translation(Quat::from_rotation_y(0.3), Vec3::new(-3.0, 0.0, 0.0)),
+            Mat4::from_scale_rotation_translation(Vec3::new(0.5, 1.5, 0.5), Quat::from_rotation_y(0.8), Vec3::ZERO),
+            Mat4::from_rotation_translation(Quat::from_rotation_x(0.6), Vec3::new(3.0, 0.0, 0.0))
+        ];
+        for transform in transforms {
+            commands.spawn().insert(cube.clone()).insert(Transform::from_mat4(transform));
+        }
+
+        commands.spawn().insert(PointLight {
+            position: Vec3::new(-2.0, 2.5, 2.0),
+            color: Vec3::new(1.0, 0.6, 0.3),
+            intensity: 6.0,
+            range: 10.0
+        });
+        commands.spawn().insert(SpotLight {
+            position: Vec3::new(3.0, 4.0, 2.0),
+            direction: Vec3::new(0.0, -4.0, -2.0),
+            color: Vec3::new(0.4, 0.6, 1.0),
+            intensity: 8.0,
+            range: 12.0,
+            inner_angle: 0.3,
+            outer_angle: 0.5
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn instanced_cubes_match_golden_image() {
+        GoldenScene { frames: 3, ..GoldenScene::new("instanced_cubes") }.assert_matches(instanced_cubes);
+    }
+}
@@ -1,27 +1,302 @@
-use bevy_input::ElementState;
+use std::path::Path;
+use bevy_ecs::prelude::*;
+use bevy_input::{ElementState, Input};
 use bevy_input::keyboard::{KeyboardInput, KeyCode};
-use winit::event::{VirtualKeyCode};
+use bevy_input::mouse::{MouseButton, MouseButtonInput, MouseMotion, MouseScrollUnit, MouseWheel};
+use winit::event::{MouseScrollDelta, VirtualKeyCode};
+use crate::app::{stage, App, Plugin, ASSETS_DIR};
+use crate::core::gamepad::GamepadPlugin;
+use crate::core::input_map::{self, ActionState, InputMap};
+use crate::core::systems;
+use crate::core::window::{CursorLeft, CursorMoved, CursorPosition};
 
+/// Adds the keyboard, mouse, cursor and gamepad state fed by the window and the gamepad backend,
+/// and the action state bound to them by `assets/input.ron`
+pub struct InputPlugin;
+
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut App) {
+        let input_map_path = Path::new(ASSETS_DIR).join(input_map::INPUT_MAP_FILE);
+        let input_map = InputMap::load(&input_map_path).unwrap_or_else(|error| {
+            log::warn!("{}, using the default bindings", error);
+            InputMap::with_default_bindings()
+        });
+
+        app.add_event::<KeyboardInput>()
+            .init_resource::<Input<KeyCode>>()
+            .add_event::<MouseMotion>()
+            .add_event::<MouseButtonInput>()
+            .add_event::<MouseWheel>()
+            .init_resource::<Input<MouseButton>>()
+            .add_event::<CursorMoved>()
+            .add_event::<CursorLeft>()
+            .init_resource::<CursorPosition>()
+            .add_plugin(GamepadPlugin)
+            .insert_resource(input_map)
+            .init_resource::<ActionState>()
+            .add_system_to_stage(stage::FIRST,
+                                 bevy_input::keyboard::keyboard_input_system.label("keyboard_input_system"))
+            .add_system_to_stage(stage::FIRST,
+                                 bevy_input::mouse::mouse_button_input_system.label("mouse_button_input_system"))
+            .add_system_to_stage(stage::FIRST, systems::update_cursor_position)
+            .add_system_to_stage(stage::FIRST, input_map::update_action_state
+                .after("keyboard_input_system")
+                .after("mouse_button_input_system")
+                .after("gamepad_event_system"));
+    }
+}
+
+/// Converts a winit key event. Events for keys winit couldn't identify have no key code.
 pub fn convert_winit_keyboard_input(&input: &winit::event::KeyboardInput) -> KeyboardInput {
-    let mut key_code = KeyCode::Return;
-    if let Some(virtual_keycode) = input.virtual_keycode {
-        key_code = match virtual_keycode {
-            VirtualKeyCode::W => KeyCode::W,
-            VirtualKeyCode::A => KeyCode::A,
-            VirtualKeyCode::S => KeyCode::S,
-            VirtualKeyCode::D => KeyCode::D,
-            _ => KeyCode::Return
-        };
-    }
-
-    let state = match input.state {
+    KeyboardInput {
+        scan_code: input.scancode,
+        key_code: input.virtual_keycode.map(convert_winit_virtual_key_code),
+        state: convert_winit_element_state(input.state)
+    }
+}
+
+/// Every winit key has a bevy counterpart of the same name. The match is exhaustive so that keys
+/// added in a winit upgrade fail to compile instead of being dropped.
+pub fn convert_winit_virtual_key_code(virtual_keycode: VirtualKeyCode) -> KeyCode {
+    match virtual_keycode {
+        VirtualKeyCode::Key1 => KeyCode::Key1,
+        VirtualKeyCode::Key2 => KeyCode::Key2,
+        VirtualKeyCode::Key3 => KeyCode::Key3,
+        VirtualKeyCode::Key4 => KeyCode::Key4,
+        VirtualKeyCode::Key5 => KeyCode::Key5,
+        VirtualKeyCode::Key6 => KeyCode::Key6,
+        VirtualKeyCode::Key7 => KeyCode::Key7,
+        VirtualKeyCode::Key8 => KeyCode::Key8,
+        VirtualKeyCode::Key9 => KeyCode::Key9,
+        VirtualKeyCode::Key0 => KeyCode::Key0,
+        VirtualKeyCode::A => KeyCode::A,
+        VirtualKeyCode::B => KeyCode::B,
+        VirtualKeyCode::C => KeyCode::C,
+        VirtualKeyCode::D => KeyCode::D,
+        VirtualKeyCode::E => KeyCode::E,
+        VirtualKeyCode::F => KeyCode::F,
+        VirtualKeyCode::G => KeyCode::G,
+        VirtualKeyCode::H => KeyCode::H,
+        VirtualKeyCode::I => KeyCode::I,
+        VirtualKeyCode::J => KeyCode::J,
+        VirtualKeyCode::K => KeyCode::K,
+        VirtualKeyCode::L => KeyCode::L,
+        VirtualKeyCode::M => KeyCode::M,
+        VirtualKeyCode::N => KeyCode::N,
+        VirtualKeyCode::O => KeyCode::O,
+        VirtualKeyCode::P => KeyCode::P,
+        VirtualKeyCode::Q => KeyCode::Q,
+        VirtualKeyCode::R => KeyCode::R,
+        VirtualKeyCode::S => KeyCode::S,
+        VirtualKeyCode::T => KeyCode::T,
+        VirtualKeyCode::U => KeyCode::U,
+        VirtualKeyCode::V => KeyCode::V,
+        VirtualKeyCode::W => KeyCode::W,
+        VirtualKeyCode::X => KeyCode::X,
+        VirtualKeyCode::Y => KeyCode::Y,
+        VirtualKeyCode::Z => KeyCode::Z,
+        VirtualKeyCode::Escape => KeyCode::Escape,
+        VirtualKeyCode::F1 => KeyCode::F1,
+        VirtualKeyCode::F2 => KeyCode::F2,
+        VirtualKeyCode::F3 => KeyCode::F3,
+        VirtualKeyCode::F4 => KeyCode::F4,
+        VirtualKeyCode::F5 => KeyCode::F5,
+        VirtualKeyCode::F6 => KeyCode::F6,
+        VirtualKeyCode::F7 => KeyCode::F7,
+        VirtualKeyCode::F8 => KeyCode::F8,
+        VirtualKeyCode::F9 => KeyCode::F9,
+        VirtualKeyCode::F10 => KeyCode::F10,
+        VirtualKeyCode::F11 => KeyCode::F11,
+        VirtualKeyCode::F12 => KeyCode::F12,
+        VirtualKeyCode::F13 => KeyCode::F13,
+        VirtualKeyCode::F14 => KeyCode::F14,
+        VirtualKeyCode::F15 => KeyCode::F15,
+        VirtualKeyCode::F16 => KeyCode::F16,
+        VirtualKeyCode::F17 => KeyCode::F17,
+        VirtualKeyCode::F18 => KeyCode::F18,
+        VirtualKeyCode::F19 => KeyCode::F19,
+        VirtualKeyCode::F20 => KeyCode::F20,
+        VirtualKeyCode::F21 => KeyCode::F21,
+        VirtualKeyCode::F22 => KeyCode::F22,
+        VirtualKeyCode::F23 => KeyCode::F23,
+        VirtualKeyCode::F24 => KeyCode::F24,
+        VirtualKeyCode::Snapshot => KeyCode::Snapshot,
+        VirtualKeyCode::Scroll => KeyCode::Scroll,
+        VirtualKeyCode::Pause => KeyCode::Pause,
+        VirtualKeyCode::Insert => KeyCode::Insert,
+        VirtualKeyCode::Home => KeyCode::Home,
+        VirtualKeyCode::Delete => KeyCode::Delete,
+        VirtualKeyCode::End => KeyCode::End,
+        VirtualKeyCode::PageDown => KeyCode::PageDown,
+        VirtualKeyCode::PageUp => KeyCode::PageUp,
+        VirtualKeyCode::Left => KeyCode::Left,
+        VirtualKeyCode::Up => KeyCode::Up,
+        VirtualKeyCode::Right => KeyCode::Right,
+        VirtualKeyCode::Down => KeyCode::Down,
+        VirtualKeyCode::Back => KeyCode::Back,
+        VirtualKeyCode::Return => KeyCode::Return,
+        VirtualKeyCode::Space => KeyCode::Space,
+        VirtualKeyCode::Compose => KeyCode::Compose,
+        VirtualKeyCode::Caret => KeyCode::Caret,
+        VirtualKeyCode::Numlock => KeyCode::Numlock,
+        VirtualKeyCode::Numpad0 => KeyCode::Numpad0,
+        VirtualKeyCode::Numpad1 => KeyCode::Numpad1,
+        VirtualKeyCode::Numpad2 => KeyCode::Numpad2,
+        VirtualKeyCode::Numpad3 => KeyCode::Numpad3,
+        VirtualKeyCode::Numpad4 => KeyCode::Numpad4,
+        VirtualKeyCode::Numpad5 => KeyCode::Numpad5,
+        VirtualKeyCode::Numpad6 => KeyCode::Numpad6,
+        VirtualKeyCode::Numpad7 => KeyCode::Numpad7,
+        VirtualKeyCode::Numpad8 => KeyCode::Numpad8,
+        VirtualKeyCode::Numpad9 => KeyCode::Numpad9,
+        VirtualKeyCode::NumpadAdd => KeyCode::NumpadAdd,
+        VirtualKeyCode::NumpadDivide => KeyCode::NumpadDivide,
+        VirtualKeyCode::NumpadDecimal => KeyCode::NumpadDecimal,
+        VirtualKeyCode::NumpadComma => KeyCode::NumpadComma,
+        VirtualKeyCode::NumpadEnter => KeyCode::NumpadEnter,
+        VirtualKeyCode::NumpadEquals => KeyCode::NumpadEquals,
+        VirtualKeyCode::NumpadMultiply => KeyCode::NumpadMultiply,
+        VirtualKeyCode::NumpadSubtract => KeyCode::NumpadSubtract,
+        VirtualKeyCode::AbntC1 => KeyCode::AbntC1,
+        VirtualKeyCode::AbntC2 => KeyCode::AbntC2,
+        VirtualKeyCode::Apostrophe => KeyCode::Apostrophe,
+        VirtualKeyCode::Apps => KeyCode::Apps,
+        VirtualKeyCode::Asterisk => KeyCode::Asterisk,
+        VirtualKeyCode::At => KeyCode::At,
+        VirtualKeyCode::Ax => KeyCode::Ax,
+        VirtualKeyCode::Backslash => KeyCode::Backslash,
+        VirtualKeyCode::Calculator => KeyCode::Calculator,
+        VirtualKeyCode::Capital => KeyCode::Capital,
+        VirtualKeyCode::Colon => KeyCode::Colon,
+        VirtualKeyCode::Comma => KeyCode::Comma,
+        VirtualKeyCode::Convert => KeyCode::Convert,
+        VirtualKeyCode::Equals => KeyCode::Equals,
+        VirtualKeyCode::Grave => KeyCode::Grave,
+        VirtualKeyCode::Kana => KeyCode::Kana,
+        VirtualKeyCode::Kanji => KeyCode::Kanji,
+        VirtualKeyCode::LAlt => KeyCode::LAlt,
+        VirtualKeyCode::LBracket => KeyCode::LBracket,
+        VirtualKeyCode::LControl => KeyCode::LControl,
+        VirtualKeyCode::LShift => KeyCode::LShift,
+        VirtualKeyCode::LWin => KeyCode::LWin,
+        VirtualKeyCode::Mail => KeyCode::Mail,
+        VirtualKeyCode::MediaSelect => KeyCode::MediaSelect,
+        VirtualKeyCode::MediaStop => KeyCode::MediaStop,
+        VirtualKeyCode::Minus => KeyCode::Minus,
+        VirtualKeyCode::Mute => KeyCode::Mute,
+        VirtualKeyCode::MyComputer => KeyCode::MyComputer,
+        VirtualKeyCode::NavigateForward => KeyCode::NavigateForward,
+        VirtualKeyCode::NavigateBackward => KeyCode::NavigateBackward,
+        VirtualKeyCode::NextTrack => KeyCode::NextTrack,
+        VirtualKeyCode::NoConvert => KeyCode::NoConvert,
+        VirtualKeyCode::OEM102 => KeyCode::Oem102,
+        VirtualKeyCode::Period => KeyCode::Period,
+        VirtualKeyCode::PlayPause => KeyCode::PlayPause,
+        VirtualKeyCode::Plus => KeyCode::Plus,
+        VirtualKeyCode::Power => KeyCode::Power,
+        VirtualKeyCode::PrevTrack => KeyCode::PrevTrack,
+        VirtualKeyCode::RAlt => KeyCode::RAlt,
+        VirtualKeyCode::RBracket => KeyCode::RBracket,
+        VirtualKeyCode::RControl => KeyCode::RControl,
+        VirtualKeyCode::RShift => KeyCode::RShift,
+        VirtualKeyCode::RWin => KeyCode::RWin,
+        VirtualKeyCode::Semicolon => KeyCode::Semicolon,
+        VirtualKeyCode::Slash => KeyCode::Slash,
+        VirtualKeyCode::Sleep => KeyCode::Sleep,
+        VirtualKeyCode::Stop => KeyCode::Stop,
+        VirtualKeyCode::Sysrq => KeyCode::Sysrq,
+        VirtualKeyCode::Tab => KeyCode::Tab,
+        VirtualKeyCode::Underline => KeyCode::Underline,
+        VirtualKeyCode::Unlabeled => KeyCode::Unlabeled,
+        VirtualKeyCode::VolumeDown => KeyCode::VolumeDown,
+        VirtualKeyCode::VolumeUp => KeyCode::VolumeUp,
+        VirtualKeyCode::Wake => KeyCode::Wake,
+        VirtualKeyCode::WebBack => KeyCode::WebBack,
+        VirtualKeyCode::WebFavorites => KeyCode::WebFavorites,
+        VirtualKeyCode::WebForward => KeyCode::WebForward,
+        VirtualKeyCode::WebHome => KeyCode::WebHome,
+        VirtualKeyCode::WebRefresh => KeyCode::WebRefresh,
+        VirtualKeyCode::WebSearch => KeyCode::WebSearch,
+        VirtualKeyCode::WebStop => KeyCode::WebStop,
+        VirtualKeyCode::Yen => KeyCode::Yen,
+        VirtualKeyCode::Copy => KeyCode::Copy,
+        VirtualKeyCode::Paste => KeyCode::Paste,
+        VirtualKeyCode::Cut => KeyCode::Cut
+    }
+}
+
+pub fn convert_winit_mouse_button_input(state: winit::event::ElementState, button: winit::event::MouseButton)
+    -> MouseButtonInput {
+    let button = match button {
+        winit::event::MouseButton::Left => MouseButton::Left,
+        winit::event::MouseButton::Right => MouseButton::Right,
+        winit::event::MouseButton::Middle => MouseButton::Middle,
+        winit::event::MouseButton::Other(index) => MouseButton::Other(index)
+    };
+
+    MouseButtonInput {
+        button,
+        state: convert_winit_element_state(state)
+    }
+}
+
+pub fn convert_winit_mouse_wheel(delta: MouseScrollDelta) -> MouseWheel {
+    match delta {
+        MouseScrollDelta::LineDelta(x, y) => MouseWheel { unit: MouseScrollUnit::Line, x, y },
+        MouseScrollDelta::PixelDelta(position) => MouseWheel {
+            unit: MouseScrollUnit::Pixel,
+            x: position.x as f32,
+            y: position.y as f32
+        }
+    }
+}
+
+fn convert_winit_element_state(state: winit::event::ElementState) -> ElementState {
+    match state {
         winit::event::ElementState::Pressed => ElementState::Pressed,
         winit::event::ElementState::Released => ElementState::Released
-    };
+    }
+}
 
-    KeyboardInput {
-        scan_code: input.scancode,
-        key_code: Some(key_code),
-        state
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn winit_key_event(virtual_keycode: Option<VirtualKeyCode>) -> winit::event::KeyboardInput {
+        #[allow(deprecated)]
+        winit::event::KeyboardInput {
+            scancode: 17,
+            state: winit::event::ElementState::Pressed,
+            virtual_keycode,
+            modifiers: Default::default()
+        }
+    }
+
+    #[test]
+    fn keys_keep_their_names() {
+        let keys = [
+            VirtualKeyCode::Key0, VirtualKeyCode::Key7, VirtualKeyCode::A, VirtualKeyCode::Z,
+            VirtualKeyCode::F1, VirtualKeyCode::F12, VirtualKeyCode::F24,
+            VirtualKeyCode::Left, VirtualKeyCode::Up, VirtualKeyCode::Right, VirtualKeyCode::Down,
+            VirtualKeyCode::LShift, VirtualKeyCode::RShift, VirtualKeyCode::LControl, VirtualKeyCode::RControl,
+            VirtualKeyCode::LAlt, VirtualKeyCode::RAlt, VirtualKeyCode::LWin, VirtualKeyCode::RWin,
+            VirtualKeyCode::Space, VirtualKeyCode::Tab, VirtualKeyCode::Escape, VirtualKeyCode::Back,
+            VirtualKeyCode::Numpad5, VirtualKeyCode::NumpadEnter, VirtualKeyCode::Grave, VirtualKeyCode::Cut
+        ];
+        for key in keys {
+            assert_eq!(format!("{:?}", convert_winit_virtual_key_code(key)), format!("{:?}", key));
+        }
+        assert_eq!(convert_winit_virtual_key_code(VirtualKeyCode::OEM102), KeyCode::Oem102);
+    }
+
+    #[test]
+    fn unidentified_keys_have_no_key_code() {
+        let event = convert_winit_keyboard_input(&winit_key_event(None));
+        assert_eq!(event.key_code, None);
+        assert_eq!(event.scan_code, 17);
+        assert_eq!(event.state, ElementState::Pressed);
+
+        let event = convert_winit_keyboard_input(&winit_key_event(Some(VirtualKeyCode::Return)));
+        assert_eq!(event.key_code, Some(KeyCode::Return));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,378 @@
+//! Gameplay reads named actions from `ActionState` instead of specific keys, so that controls can
+//! be rebound. `InputMap` holds the bindings and is loaded from `assets/input.ron`; it can be
+//! changed at runtime and takes effect on the next frame.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use bevy_ecs::prelude::*;
+use bevy_input::{Axis, Input};
+use bevy_input::gamepad::{GamepadAxis, GamepadAxisType, GamepadButton, GamepadButtonType, Gamepads};
+use bevy_input::keyboard::KeyCode;
+use bevy_input::mouse::MouseButton;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+/// The file in the assets directory that bindings are loaded from
+pub const INPUT_MAP_FILE: &str = "input.ron";
+
+/// Moving across the ground, with y forward
+pub const MOVE: &str = "move";
+/// Moving straight up or down
+pub const MOVE_VERTICAL: &str = "move_vertical";
+pub const SPRINT: &str = "sprint";
+pub const TOGGLE_CAMERA_MODE: &str = "toggle_camera_mode";
+pub const FRAME_SELECTED: &str = "frame_selected";
+pub const ORBIT_ROTATE: &str = "orbit_rotate";
+pub const ORBIT_PAN: &str = "orbit_pan";
+
+/// Something that can be held down
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InputSource {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    /// The button on any connected gamepad
+    GamepadButton(GamepadButtonType)
+}
+
+/// Something that reads from -1 to 1
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AxisBinding {
+    /// -1 while `negative` is held and 1 while `positive` is, or 0 while both or neither are
+    Digital { negative: InputSource, positive: InputSource },
+    /// The axis on any connected gamepad, after its dead zone is applied
+    GamepadAxis(GamepadAxisType)
+}
+
+/// Two axes read together, such as WASD or a stick
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VectorBinding {
+    pub x: AxisBinding,
+    pub y: AxisBinding
+}
+
+impl VectorBinding {
+    pub fn keys(up: KeyCode, down: KeyCode, left: KeyCode, right: KeyCode) -> Self {
+        Self {
+            x: AxisBinding::Digital { negative: InputSource::Key(left), positive: InputSource::Key(right) },
+            y: AxisBinding::Digital { negative: InputSource::Key(down), positive: InputSource::Key(up) }
+        }
+    }
+
+    pub fn gamepad_stick(x: GamepadAxisType, y: GamepadAxisType) -> Self {
+        Self { x: AxisBinding::GamepadAxis(x), y: AxisBinding::GamepadAxis(y) }
+    }
+}
+
+/// Which inputs trigger each action. An action can have several bindings, and is active while any
+/// of them is.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputMap {
+    pub buttons: BTreeMap<String, Vec<InputSource>>,
+    pub axes: BTreeMap<String, Vec<AxisBinding>>,
+    pub vectors: BTreeMap<String, Vec<VectorBinding>>
+}
+
+impl InputMap {
+    /// The bindings used when `assets/input.ron` is missing
+    pub fn with_default_bindings() -> Self {
+        let mut map = Self::default();
+        map.bind_vector(MOVE, VectorBinding::keys(KeyCode::W, KeyCode::S, KeyCode::A, KeyCode::D))
+            .bind_vector(MOVE, VectorBinding::gamepad_stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY))
+            .bind_axis(MOVE_VERTICAL, AxisBinding::Digital {
+                negative: InputSource::Key(KeyCode::Q),
+                positive: InputSource::Key(KeyCode::E)
+            })
+            .bind_axis(MOVE_VERTICAL, AxisBinding::Digital {
+                negative: InputSource::GamepadButton(GamepadButtonType::LeftTrigger),
+                positive: InputSource::GamepadButton(GamepadButtonType::RightTrigger)
+            })
+            .bind(SPRINT, InputSource::Key(KeyCode::LShift))
+            .bind(SPRINT, InputSource::GamepadButton(GamepadButtonType::LeftThumb))
+            .bind(TOGGLE_CAMERA_MODE, InputSource::Key(KeyCode::Tab))
+            .bind(TOGGLE_CAMERA_MODE, InputSource::GamepadButton(GamepadButtonType::Select))
+            .bind(FRAME_SELECTED, InputSource::Key(KeyCode::F))
+            .bind(FRAME_SELECTED, InputSource::GamepadButton(GamepadButtonType::North))
+            .bind(ORBIT_ROTATE, InputSource::Mouse(MouseButton::Left))
+            .bind(ORBIT_PAN, InputSource::Mouse(MouseButton::Middle));
+        map
+    }
+
+    /// Adds a binding to a button action, unless it is already bound
+    pub fn bind(&mut self, action: &str, source: InputSource) -> &mut Self {
+        let sources = self.buttons.entry(action.to_string()).or_default();
+        if !sources.contains(&source) {
+            sources.push(source);
+        }
+        self
+    }
+
+    pub fn bind_axis(&mut self, action: &str, binding: AxisBinding) -> &mut Self {
+        let bindings = self.axes.entry(action.to_string()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+        self
+    }
+
+    pub fn bind_vector(&mut self, action: &str, binding: VectorBinding) -> &mut Self {
+        let bindings = self.vectors.entry(action.to_string()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+        self
+    }
+
+    pub fn unbind(&mut self, action: &str, source: InputSource) {
+        if let Some(sources) = self.buttons.get_mut(action) {
+            sources.retain(|bound| *bound != source);
+        }
+    }
+
+    /// Replaces every binding of a button action with `source`, such as when a player picks a new key
+    pub fn rebind(&mut self, action: &str, source: InputSource) {
+        self.buttons.insert(action.to_string(), vec![source]);
+    }
+
+    pub fn from_ron(ron: &str) -> Result<Self, ron::Error> {
+        ron::from_str(ron)
+    }
+
+    pub fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())
+            .expect("Bindings are always representable in RON")
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, InputMapError> {
+        let path = path.as_ref();
+        let ron = std::fs::read_to_string(path)
+            .map_err(|source| InputMapError::Io { path: path.to_path_buf(), source })?;
+        Self::from_ron(&ron).map_err(|source| InputMapError::Parse { path: path.to_path_buf(), source })
+    }
+
+    /// Writes the bindings so that rebound controls persist
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), InputMapError> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_ron())
+            .map_err(|source| InputMapError::Io { path: path.to_path_buf(), source })
+    }
+}
+
+#[derive(Debug)]
+pub enum InputMapError {
+    Io { path: PathBuf, source: io::Error },
+    Parse { path: PathBuf, source: ron::Error }
+}
+
+impl fmt::Display for InputMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            InputMapError::Io { path, .. } => write!(f, "Unable to access input bindings {}", path.display()),
+            InputMapError::Parse { path, source } =>
+                write!(f, "Unable to parse input bindings {}: {}", path.display(), source)
+        }
+    }
+}
+
+impl Error for InputMapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InputMapError::Io { source, .. } => Some(source),
+            InputMapError::Parse { source, .. } => Some(source)
+        }
+    }
+}
+
+/// The state of every action this frame, derived from the `InputMap` and the raw input resources
+#[derive(Default)]
+pub struct ActionState {
+    pressed: HashSet<String>,
+    just_pressed: HashSet<String>,
+    just_released: HashSet<String>,
+    axes: HashMap<String, f32>,
+    vectors: HashMap<String, Vec2>
+}
+
+impl ActionState {
+    pub fn pressed(&self, action: &str) -> bool {
+        self.pressed.contains(action)
+    }
+
+    pub fn just_pressed(&self, action: &str) -> bool {
+        self.just_pressed.contains(action)
+    }
+
+    pub fn just_released(&self, action: &str) -> bool {
+        self.just_released.contains(action)
+    }
+
+    /// From -1 to 1, or 0 for actions that aren't bound
+    pub fn axis(&self, action: &str) -> f32 {
+        self.axes.get(action).copied().unwrap_or(0.0)
+    }
+
+    /// At most 1 long, so that diagonals aren't faster than straight lines
+    pub fn vector(&self, action: &str) -> Vec2 {
+        self.vectors.get(action).copied().unwrap_or(Vec2::ZERO)
+    }
+
+    fn update(&mut self, map: &InputMap, input: &RawInput) {
+        let pressed: HashSet<String> = map.buttons.iter()
+            .filter(|(_, sources)| sources.iter().any(|source| input.pressed(*source)))
+            .map(|(action, _)| action.clone())
+            .collect();
+        self.just_pressed = pressed.difference(&self.pressed).cloned().collect();
+        self.just_released = self.pressed.difference(&pressed).cloned().collect();
+        self.pressed = pressed;
+
+        self.axes = map.axes.iter()
+            .map(|(action, bindings)| {
+                let value: f32 = bindings.iter().map(|binding| input.axis(binding)).sum();
+                (action.clone(), value.clamp(-1.0, 1.0))
+            })
+            .collect();
+        self.vectors = map.vectors.iter()
+            .map(|(action, bindings)| {
+                let value = bindings.iter().fold(Vec2::ZERO, |value, binding|
+                    value + Vec2::new(input.axis(&binding.x), input.axis(&binding.y)));
+                (action.clone(), value.clamp_length_max(1.0))
+            })
+            .collect();
+    }
+}
+
+/// The input resources actions are read from
+struct RawInput<'a> {
+    keys: &'a Input<KeyCode>,
+    mouse_buttons: &'a Input<MouseButton>,
+    gamepads: &'a Gamepads,
+    gamepad_buttons: &'a Input<GamepadButton>,
+    gamepad_axes: &'a Axis<GamepadAxis>
+}
+
+impl RawInput<'_> {
+    fn pressed(&self, source: InputSource) -> bool {
+        match source {
+            InputSource::Key(key_code) => self.keys.pressed(key_code),
+            InputSource::Mouse(button) => self.mouse_buttons.pressed(button),
+            InputSource::GamepadButton(button) => self.gamepads.iter()
+                .any(|&gamepad| self.gamepad_buttons.pressed(GamepadButton(gamepad, button)))
+        }
+    }
+
+    fn axis(&self, binding: &AxisBinding) -> f32 {
+        match *binding {
+            AxisBinding::Digital { negative, positive } =>
+                self.pressed(positive) as i32 as f32 - self.pressed(negative) as i32 as f32,
+            AxisBinding::GamepadAxis(axis) => self.gamepads.iter()
+                .filter_map(|&gamepad| self.gamepad_axes.get(GamepadAxis(gamepad, axis)))
+                .sum()
+        }
+    }
+}
+
+pub(crate) fn update_action_state(map: Res<InputMap>, mut state: ResMut<ActionState>, keys: Res<Input<KeyCode>>,
+                                  mouse_buttons: Res<Input<MouseButton>>, gamepads: Res<Gamepads>,
+                                  gamepad_buttons: Res<Input<GamepadButton>>, gamepad_axes: Res<Axis<GamepadAxis>>) {
+    state.update(&map, &RawInput {
+        keys: &keys,
+        mouse_buttons: &mouse_buttons,
+        gamepads: &gamepads,
+        gamepad_buttons: &gamepad_buttons,
+        gamepad_axes: &gamepad_axes
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app;
+    use super::*;
+
+    #[derive(Default)]
+    struct TestInput {
+        keys: Input<KeyCode>,
+        mouse_buttons: Input<MouseButton>,
+        gamepads: Gamepads,
+        gamepad_buttons: Input<GamepadButton>,
+        gamepad_axes: Axis<GamepadAxis>
+    }
+
+    impl TestInput {
+        fn update(&self, map: &InputMap, state: &mut ActionState) {
+            state.update(map, &RawInput {
+                keys: &self.keys,
+                mouse_buttons: &self.mouse_buttons,
+                gamepads: &self.gamepads,
+                gamepad_buttons: &self.gamepad_buttons,
+                gamepad_axes: &self.gamepad_axes
+            });
+        }
+    }
+
+    #[test]
+    fn buttons_report_edges_once() {
+        let map = InputMap::with_default_bindings();
+        let mut input = TestInput::default();
+        let mut state = ActionState::default();
+
+        input.keys.press(KeyCode::LShift);
+        input.update(&map, &mut state);
+        assert!(state.pressed(SPRINT) && state.just_pressed(SPRINT));
+        input.update(&map, &mut state);
+        assert!(state.pressed(SPRINT) && !state.just_pressed(SPRINT));
+
+        input.keys.release(KeyCode::LShift);
+        input.update(&map, &mut state);
+        assert!(!state.pressed(SPRINT) && state.just_released(SPRINT));
+    }
+
+    #[test]
+    fn key_composites_make_unit_vectors() {
+        let map = InputMap::with_default_bindings();
+        let mut input = TestInput::default();
+        let mut state = ActionState::default();
+
+        input.keys.press(KeyCode::W);
+        input.keys.press(KeyCode::D);
+        input.update(&map, &mut state);
+        let expected = Vec2::new(1.0, 1.0).normalize();
+        assert!(state.vector(MOVE).abs_diff_eq(expected, 1e-6), "moved {:?}", state.vector(MOVE));
+
+        input.keys.press(KeyCode::A);
+        input.update(&map, &mut state);
+        assert_eq!(state.vector(MOVE), Vec2::Y);
+        assert_eq!(state.axis(MOVE_VERTICAL), 0.0);
+    }
+
+    #[test]
+    fn rebinding_replaces_the_old_bindings() {
+        let mut map = InputMap::with_default_bindings();
+        map.rebind(SPRINT, InputSource::Key(KeyCode::LControl));
+        let mut input = TestInput::default();
+        let mut state = ActionState::default();
+
+        input.keys.press(KeyCode::LShift);
+        input.update(&map, &mut state);
+        assert!(!state.pressed(SPRINT));
+        input.keys.press(KeyCode::LControl);
+        input.update(&map, &mut state);
+        assert!(state.pressed(SPRINT));
+    }
+
+    #[test]
+    fn bindings_round_trip_through_ron() {
+        let mut map = InputMap::with_default_bindings();
+        map.bind("jump", InputSource::GamepadButton(GamepadButtonType::South));
+        assert_eq!(InputMap::from_ron(&map.to_ron()).unwrap(), map);
+        assert!(InputMap::from_ron("(buttons: {\"jump\": [Key(Space)]})").unwrap().axes.is_empty());
+    }
+
+    #[test]
+    fn shipped_bindings_are_the_defaults() {
+        let map = InputMap::load(Path::new(app::ASSETS_DIR).join(INPUT_MAP_FILE)).unwrap();
+        assert_eq!(map, InputMap::with_default_bindings());
+    }
+}
@@ -1,3 +1,11 @@
 pub mod systems;
 pub mod input;
 pub mod time;
+pub mod timer;
+pub mod window;
+pub mod camera_controller;
+pub mod gamepad;
+pub mod input_map;
+pub mod recording;
+#[cfg(test)]
+pub mod golden;
@@ -0,0 +1,330 @@
+//! Records the input fed into the ECS each frame, along with the frame's time step, so that a run
+//! can be replayed exactly: to reproduce a bug report, or to drive a gameplay smoke test headless.
+//!
+//! A recording is a header followed by one bincode encoded `RecordedFrame` per frame. Frames are
+//! written as they happen, so a recording survives the game crashing.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use bevy_ecs::prelude::*;
+use bevy_input::ElementState;
+use bevy_input::keyboard::{KeyboardInput, KeyCode};
+use serde::{Deserialize, Serialize};
+use crate::app::{stage, App, Plugin};
+use crate::core::time::Time;
+
+const MAGIC: [u8; 4] = *b"CINR";
+/// Bumped whenever `RecordedFrame` changes, since older recordings can't be read after that
+const VERSION: u32 = 1;
+
+/// Where input comes from for a run
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum InputMode {
+    #[default]
+    Live,
+    /// Live input, also written to a recording at this path
+    Record(PathBuf),
+    /// Input read from a recording instead of the window, until the recording ends
+    Replay(PathBuf)
+}
+
+impl InputMode {
+    /// Reads `--record <path>` or `--replay <path>` from the command line arguments
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut mode = InputMode::Live;
+        while let Some(arg) = args.next() {
+            let to_mode: fn(PathBuf) -> InputMode = match arg.as_str() {
+                "--record" => InputMode::Record,
+                "--replay" => InputMode::Replay,
+                _ => return Err(format!("Unknown argument {}", arg))
+            };
+            let path = args.next().ok_or_else(|| format!("{} needs a file path", arg))?;
+            mode = to_mode(PathBuf::from(path));
+        }
+        Ok(mode)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    magic: [u8; 4],
+    version: u32
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// The unscaled time step, so that the time scale set during the replay applies on top of it
+    pub delta_seconds: f32,
+    pub keyboard: Vec<RecordedKey>
+}
+
+/// A `KeyboardInput` event
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedKey {
+    pub scan_code: u32,
+    pub key_code: Option<KeyCode>,
+    pub state: ElementState
+}
+
+impl From<&KeyboardInput> for RecordedKey {
+    fn from(input: &KeyboardInput) -> Self {
+        Self { scan_code: input.scan_code, key_code: input.key_code, state: input.state }
+    }
+}
+
+impl From<RecordedKey> for KeyboardInput {
+    fn from(key: RecordedKey) -> Self {
+        Self { scan_code: key.scan_code, key_code: key.key_code, state: key.state }
+    }
+}
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io { path: PathBuf, source: io::Error },
+    /// The file isn't a recording, or was made by an incompatible version
+    Format { path: PathBuf, reason: String },
+    Encoding { path: PathBuf, source: bincode::Error }
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            RecordingError::Io { path, .. } => write!(f, "Unable to access input recording {}", path.display()),
+            RecordingError::Format { path, reason } =>
+                write!(f, "Input recording {} can't be replayed: {}", path.display(), reason),
+            RecordingError::Encoding { path, .. } => write!(f, "Input recording {} is corrupt", path.display())
+        }
+    }
+}
+
+impl Error for RecordingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RecordingError::Io { source, .. } => Some(source),
+            RecordingError::Format { .. } => None,
+            RecordingError::Encoding { source, .. } => Some(source.as_ref())
+        }
+    }
+}
+
+/// Writes every frame's input to a recording while it is a resource
+pub struct InputRecorder {
+    path: PathBuf,
+    writer: BufWriter<File>
+}
+
+impl InputRecorder {
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self, RecordingError> {
+        let path = path.into();
+        let file = File::create(&path).map_err(|source| RecordingError::Io { path: path.clone(), source })?;
+        let mut recorder = Self { path, writer: BufWriter::new(file) };
+        recorder.write(&Header { magic: MAGIC, version: VERSION })?;
+        Ok(recorder)
+    }
+
+    pub fn record(&mut self, frame: &RecordedFrame) -> Result<(), RecordingError> {
+        self.write(frame)?;
+        self.writer.flush().map_err(|source| RecordingError::Io { path: self.path.clone(), source })
+    }
+
+    fn write(&mut self, value: &impl Serialize) -> Result<(), RecordingError> {
+        bincode::serialize_into(&mut self.writer, value)
+            .map_err(|source| RecordingError::Encoding { path: self.path.clone(), source })
+    }
+}
+
+/// The frames of a recording still to be replayed. While this is a resource, input comes from it
+/// instead of the window; it removes itself once every frame has been replayed.
+pub struct InputReplay {
+    frames: VecDeque<RecordedFrame>
+}
+
+impl InputReplay {
+    pub fn new(frames: impl IntoIterator<Item = RecordedFrame>) -> Self {
+        Self { frames: frames.into_iter().collect() }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|source| RecordingError::Io { path: path.to_path_buf(), source })?;
+        let mut reader = BufReader::new(file);
+        let encoding_error = |source| RecordingError::Encoding { path: path.to_path_buf(), source };
+
+        let header: Header = bincode::deserialize_from(&mut reader).map_err(encoding_error)?;
+        if header.magic != MAGIC {
+            return Err(RecordingError::Format { path: path.to_path_buf(), reason: "not an input recording".to_string() });
+        }
+        if header.version != VERSION {
+            return Err(RecordingError::Format {
+                path: path.to_path_buf(),
+                reason: format!("recorded with format version {}, but only version {} is supported", header.version, VERSION)
+            });
+        }
+
+        let mut frames = VecDeque::new();
+        loop {
+            match bincode::deserialize_from(&mut reader) {
+                Ok(frame) => frames.push_back(frame),
+                // The last frame may be cut short if the recording game crashed while writing it
+                Err(error) if matches!(*error, bincode::ErrorKind::Io(ref io_error)
+                    if io_error.kind() == io::ErrorKind::UnexpectedEof) => break,
+                Err(error) => return Err(encoding_error(error))
+            }
+        }
+        Ok(Self { frames })
+    }
+
+    pub fn remaining_frames(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+/// Records the input of every frame while there is an `InputRecorder`, and feeds input and time
+/// steps from an `InputReplay` while there is one. Both are inserted with `insert_input_mode`.
+pub struct RecordingPlugin;
+
+impl Plugin for RecordingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(stage::FIRST, replay_input
+                .label("replay_input")
+                .before("time_system")
+                .before("keyboard_input_system"))
+            .add_system_to_stage(stage::FIRST, record_input.after("time_system"));
+    }
+}
+
+/// Inserts the recorder or replay that `input_mode` asks for
+pub fn insert_input_mode(world: &mut World, input_mode: &InputMode) -> Result<(), RecordingError> {
+    match input_mode {
+        InputMode::Live => {},
+        InputMode::Record(path) => world.insert_resource(InputRecorder::create(path)?),
+        InputMode::Replay(path) => {
+            let replay = InputReplay::load(path)?;
+            log::info!("Replaying {} frames from {}", replay.remaining_frames(), path.display());
+            world.insert_resource(replay)
+        }
+    }
+    Ok(())
+}
+
+/// Runs after the time and keyboard events of the frame are known
+pub(crate) fn record_input(recorder: Option<ResMut<InputRecorder>>, time: Res<Time>,
+                           mut keyboard_events: EventReader<KeyboardInput>, mut commands: Commands) {
+    let mut recorder = match recorder {
+        Some(recorder) => recorder,
+        None => return
+    };
+    let frame = RecordedFrame {
+        delta_seconds: time.unscaled_delta_seconds(),
+        keyboard: keyboard_events.iter().map(RecordedKey::from).collect()
+    };
+    if let Err(error) = recorder.record(&frame) {
+        log::error!("{}, stopping the recording", error);
+        commands.remove_resource::<InputRecorder>();
+    }
+}
+
+/// Sends the recorded keyboard input of the frame, and makes the time system advance by the
+/// recorded time step instead of measuring the frame
+pub(crate) fn replay_input(replay: Option<ResMut<InputReplay>>, mut time: ResMut<Time>,
+                           mut keyboard_events: EventWriter<KeyboardInput>, mut commands: Commands) {
+    let mut replay = match replay {
+        Some(replay) => replay,
+        None => return
+    };
+    if let Some(frame) = replay.frames.pop_front() {
+        time.set_next_delta(Duration::from_secs_f32(frame.delta_seconds));
+        keyboard_events.send_batch(frame.keyboard.into_iter().map(KeyboardInput::from));
+    }
+    // Removed along with the last frame, so that no frame runs on measured time
+    if replay.frames.is_empty() {
+        log::info!("Input replay finished");
+        commands.remove_resource::<InputReplay>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::event::Events;
+    use crate::core::time::time_system;
+    use super::*;
+
+    fn frames() -> Vec<RecordedFrame> {
+        vec![
+            RecordedFrame {
+                delta_seconds: 0.016,
+                keyboard: vec![RecordedKey { scan_code: 17, key_code: Some(KeyCode::W), state: ElementState::Pressed }]
+            },
+            RecordedFrame { delta_seconds: 0.017, keyboard: Vec::new() },
+            RecordedFrame {
+                delta_seconds: 0.015,
+                keyboard: vec![RecordedKey { scan_code: 0, key_code: None, state: ElementState::Released }]
+            }
+        ]
+    }
+
+    fn recording_path(name: &str) -> PathBuf {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("recordings");
+        std::fs::create_dir_all(&directory).unwrap();
+        directory.join(name)
+    }
+
+    #[test]
+    fn recordings_round_trip() {
+        let path = recording_path("round_trip.rec");
+        let mut recorder = InputRecorder::create(&path).unwrap();
+        for frame in frames() {
+            recorder.record(&frame).unwrap();
+        }
+        drop(recorder);
+
+        let replay = InputReplay::load(&path).unwrap();
+        assert_eq!(Vec::from(replay.frames), frames());
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let path = recording_path("not_a_recording.rec");
+        std::fs::write(&path, b"definitely not a recording").unwrap();
+        assert!(matches!(InputReplay::load(&path), Err(RecordingError::Format { .. })));
+    }
+
+    #[test]
+    fn replay_drives_time_and_keyboard_until_it_ends() {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<Events<KeyboardInput>>();
+        world.insert_resource(InputReplay::new(frames()));
+        let mut stage = SystemStage::parallel()
+            .with_system(replay_input.label("replay_input"))
+            .with_system(time_system.after("replay_input"));
+
+        stage.run(&mut world);
+        assert_eq!(world.get_resource::<Time>().unwrap().delta_seconds(), 0.016);
+        let keyboard_events = world.get_resource::<Events<KeyboardInput>>().unwrap();
+        let mut reader = keyboard_events.get_reader();
+        let sent: Vec<_> = reader.iter(keyboard_events).map(|event| event.key_code).collect();
+        assert_eq!(sent, [Some(KeyCode::W)]);
+
+        stage.run(&mut world);
+        assert!(world.contains_resource::<InputReplay>());
+        stage.run(&mut world);
+        assert_eq!(world.get_resource::<Time>().unwrap().delta_seconds(), 0.015);
+        assert!(!world.contains_resource::<InputReplay>());
+    }
+
+    #[test]
+    fn modes_are_read_from_arguments() {
+        let args = |args: &[&str]| InputMode::from_args(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(args(&[]), Ok(InputMode::Live));
+        assert_eq!(args(&["--replay", "bug.rec"]), Ok(InputMode::Replay(PathBuf::from("bug.rec"))));
+        assert!(args(&["--record"]).is_err());
+        assert!(args(&["--fast"]).is_err());
+    }
+}
@@ -1,50 +1,60 @@
 use bevy_ecs::prelude::*;
-use bevy_input::keyboard::{KeyboardInput, KeyCode};
-use glam::{Vec3, Mat4};
+use glam::Vec3;
 use wgpu::{BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType, BufferUsages, Device, Queue, ShaderStages, Surface, SurfaceConfiguration};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
-use crate::core::time::Time;
-use crate::renderer::camera::{Camera, CameraUniform};
-use crate::renderer::model::{DrawModel, Model};
-use crate::renderer::pipeline::{create_wgpu_render_pipeline, RenderPipeline};
+use crate::core::window::{CursorLeft, CursorMoved, CursorPosition, WindowResized};
+use crate::renderer::camera::{orientation_looking_at, Camera, CameraUniform};
+use crate::renderer::capture::{CaptureFrame, FrameCapture};
+use crate::renderer::instance::{batch_instances, InstanceBuffer, InstancedModel, InstanceRaw};
+use crate::renderer::light::{gather_lights, AmbientLight, DirectionalLight, LightBuffer, PointLight, SpotLight};
+use crate::renderer::model::{DrawModel, Model, ModelVertex};
+use crate::renderer::model::material::Material;
+use crate::renderer::offscreen::OffscreenTarget;
+use crate::renderer::pipeline::{create_wgpu_render_pipeline, RenderPipeline, Vertex, WorldTransformBuffer, WorldTransformRaw};
 use crate::renderer::texture::Texture;
 use crate::renderer::Transform;
 
-pub struct Count(pub usize);
-
-pub(crate) fn counter(mut count: ResMut<Count>, mut input_events: EventReader<KeyboardInput>) {
-    count.0 = count.0 + 1;
-    let mut num_events = 0;
-    for event in input_events.iter() {
-        match event.key_code.unwrap() {
-            KeyCode::W => println!("Key event: W"),
-            KeyCode::A => println!("Key event: A"),
-            KeyCode::S => println!("Key event: S"),
-            KeyCode::D => println!("Key event: D"),
-            KeyCode::Return => {
-                println!("Key event: Return")
-            },
-            _ => println!("Unknown key event")
-        }
-        num_events += 1;
+pub(crate) fn update_camera_uniforms(mut cameras: Query<&mut Camera>) {
+    for mut camera in cameras.iter_mut() {
+        camera.update_uniform();
+    }
+}
+
+pub(crate) fn resize_render_targets(device: Res<Device>, mut render_pipeline: ResMut<RenderPipeline>,
+                                    mut resize_events: EventReader<WindowResized>) {
+    // Only the latest size matters when several resizes happen within one frame
+    if let Some(resized) = resize_events.iter().last() {
+        render_pipeline.resize(&device, resized.width, resized.height);
     }
+}
 
-    if num_events > 0 {
-        println!("Num events: {}", num_events);
+pub(crate) fn resize_cameras(mut cameras: Query<&mut Camera>, mut resize_events: EventReader<WindowResized>) {
+    if let Some(resized) = resize_events.iter().last() {
+        for mut camera in cameras.iter_mut() {
+            camera.resize(resized.width, resized.height);
+        }
     }
 }
 
-pub(crate) fn camera_control(mut query: Query<&mut Camera>, mut input_events: EventReader<KeyboardInput>, time: Res<Time>) {
-    let mut key_inputs = Vec::new();
-    for event in input_events.iter() {
-        key_inputs.push(event.key_code.unwrap_or(KeyCode::Return))
+pub(crate) fn update_cursor_position(mut cursor_position: ResMut<CursorPosition>,
+                                     mut cursor_moved_events: EventReader<CursorMoved>,
+                                     mut cursor_left_events: EventReader<CursorLeft>) {
+    // Winit sends no further moves once the cursor has left, so leaving wins over moves in the same frame
+    if let Some(moved) = cursor_moved_events.iter().last() {
+        cursor_position.0 = Some(moved.position);
     }
-    for mut camera in query.iter_mut() {
-        camera.update(&key_inputs, time.delta_seconds);
+    if cursor_left_events.iter().count() > 0 {
+        cursor_position.0 = None;
     }
 }
 
 pub(crate) fn renderer_startup(mut commands: Commands, device: Res<Device>, config: Res<SurfaceConfiguration>) {
+    let (render_pipeline, camera) = create_render_pipeline(&device, &config);
+    commands.insert_resource(render_pipeline);
+    commands.spawn().insert(camera);
+}
+
+pub(crate) fn create_render_pipeline(device: &Device, config: &SurfaceConfiguration) -> (RenderPipeline, Camera) {
     /*
     let texture = Texture::from_bytes(
         &device,
@@ -54,31 +64,7 @@ pub(crate) fn renderer_startup(mut commands: Commands, device: Res<Device>, conf
     ).unwrap();
     */
 
-    let diffuse_bind_group_layout = device.create_bind_group_layout(
-        &BindGroupLayoutDescriptor {
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::FRAGMENT,
-                    // This should match the filterable field of the
-                    // corresponding Texture entry above.
-                    ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
-            label: Some("texture_bind_group_layout"),
-        }
-    );
+    let material_bind_group_layout = Material::create_bind_group_layout(device);
 
     let world_transform_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
         label: Some("World Transform Bind Group Layout"),
@@ -87,32 +73,19 @@ pub(crate) fn renderer_startup(mut commands: Commands, device: Res<Device>, conf
             visibility: ShaderStages::VERTEX,
             ty: BindingType::Buffer {
                 ty: BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: None
+                has_dynamic_offset: true,
+                min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<WorldTransformRaw>() as u64)
             },
             count: None
         }]
     });
 
-    let world_transform_buffer = device.create_buffer_init(&BufferInitDescriptor{
-        label: Some("World Transform Uniform Buffer"),
-        contents: bytemuck::bytes_of(&Mat4::IDENTITY),
-        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
-    });
-
-    let world_transform_bind_group = device.create_bind_group(&BindGroupDescriptor {
-        label: Some("World Transform Bind Group"),
-        layout: &world_transform_bind_group_layout,
-        entries: &[BindGroupEntry{
-            binding: 0,
-            resource: world_transform_buffer.as_entire_binding()
-        }]
-    });
+    let world_transforms = WorldTransformBuffer::new(device, &world_transform_bind_group_layout);
 
     /*
     let diffuse_bind_group = device.create_bind_group(
         &BindGroupDescriptor {
-            layout: &diffuse_bind_group_layout,
+            layout: &material_bind_group_layout,
             entries: &[
                 BindGroupEntry {
                     binding: 0,
@@ -165,21 +138,11 @@ pub(crate) fn renderer_startup(mut commands: Commands, device: Res<Device>, conf
             usage: BufferUsages::INDEX
         }
     );
-
-    let instances = Instance::raw_test_instances();
-
-    let instance_buffer = device.create_buffer_init(
-        &BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&instances),
-            usage: BufferUsages::VERTEX
-        }
-    );
      */
 
     let camera_matrix_buffer = device.create_buffer_init(&BufferInitDescriptor{
         label: Some("Camera Buffer"),
-        contents: bytemuck::bytes_of(&Mat4::IDENTITY),
+        contents: bytemuck::bytes_of(&CameraUniform::new()),
         usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
     });
 
@@ -187,7 +150,7 @@ pub(crate) fn renderer_startup(mut commands: Commands, device: Res<Device>, conf
         label: Some("Camera Bind Group Layout"),
         entries: &[BindGroupLayoutEntry {
             binding: 0,
-            visibility: ShaderStages::VERTEX,
+            visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
             ty: BindingType::Buffer {
                 ty: BufferBindingType::Uniform,
                 has_dynamic_offset: false,
@@ -206,65 +169,154 @@ pub(crate) fn renderer_startup(mut commands: Commands, device: Res<Device>, conf
         }]
     });
 
-    let camera = Camera {
-        eye: (0.0, 1.0, 2.0).into(),
-        target: (0.0, 0.0, 0.0).into(),
-        up: Vec3::Y,
+    let eye = Vec3::new(0.0, 1.0, 2.0);
+    let mut camera = Camera {
+        position: eye,
+        orientation: orientation_looking_at(eye, Vec3::ZERO, Vec3::Y),
         aspect: config.width as f32 / config.height as f32,
-        fov_y: 45.0,
+        fov_y: 45.0_f32.to_radians(),
         z_near: 0.1,
         z_far: 100.0,
-        speed: 10.0,
         uniform: CameraUniform::new(),
         buffer: camera_matrix_buffer,
         bind_group: camera_bind_group
     };
+    camera.update_uniform();
 
+    let light_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Light Bind Group Layout"),
+        entries: &[BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None
+            },
+            count: None
+        }]
+    });
+
+    let lights = LightBuffer::new(device, &light_bind_group_layout);
+
+    // Instanced draws take their world transform from the instance buffer instead of group 2, but
+    // both pipelines share this layout so the light bind group keeps the same index
     let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label: Some("Render Pipeline Layout"),
-        bind_group_layouts: &[&diffuse_bind_group_layout, &camera_bind_group_layout, &world_transform_bind_group_layout],
+        bind_group_layouts: &[&material_bind_group_layout, &camera_bind_group_layout,
+            &world_transform_bind_group_layout, &light_bind_group_layout],
         push_constant_ranges: &[]
     });
 
     let wgpu_render_pipeline = create_wgpu_render_pipeline(
-        render_pipeline_layout,
-        shader,
-        &device,
-        &config
+        "Render Pipeline",
+        &render_pipeline_layout,
+        &shader,
+        device,
+        config,
+        "vertex_shader_main",
+        &[ModelVertex::buffer_layout_description()]
     );
 
-    let depth_texture = Texture::create_depth_texture(&device, &config, "depth_texture");
+    let wgpu_instanced_render_pipeline = create_wgpu_render_pipeline(
+        "Instanced Render Pipeline",
+        &render_pipeline_layout,
+        &shader,
+        device,
+        config,
+        "vertex_shader_instanced_main",
+        &[ModelVertex::buffer_layout_description(), InstanceRaw::buffer_layout_description()]
+    );
+
+    let depth_texture = Texture::create_depth_texture(device, config.width, config.height, "depth_texture");
 
     let render_pipeline = RenderPipeline {
         wgpu_render_pipeline,
-        diffuse_bind_group_layout,
+        wgpu_instanced_render_pipeline,
+        material_bind_group_layout,
         depth_texture,
         camera_bind_group_layout,
         world_transform_bind_group_layout,
-        world_transform_bind_group,
-        world_transform_buffer
+        world_transforms,
+        instances: InstanceBuffer::new(device),
+        light_bind_group_layout,
+        lights
     };
 
-    commands.insert_resource(render_pipeline);
-    commands.spawn().insert(camera);
+    (render_pipeline, camera)
+}
+
+pub(crate) fn prepare_lights(device: Res<Device>, queue: Res<Queue>, mut render_pipeline: ResMut<RenderPipeline>,
+                             ambient_light: Res<AmbientLight>, directional_lights: Query<&DirectionalLight>,
+                             point_lights: Query<&PointLight>, spot_lights: Query<&SpotLight>) {
+    let lights = gather_lights(directional_lights.iter(), point_lights.iter(), spot_lights.iter());
+    let RenderPipeline { lights: light_buffer, light_bind_group_layout, .. } = &mut *render_pipeline;
+    light_buffer.write(&device, &queue, light_bind_group_layout, &ambient_light, &lights);
+}
+
+/// Renders to the window surface, or to the `OffscreenTarget` when running headless, and saves the
+/// frame if a capture was requested or a sequence is being recorded. Panics if there is neither.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn render(surface: Option<Res<Surface>>, offscreen_target: Option<Res<OffscreenTarget>>,
+                     config: Res<SurfaceConfiguration>, device: Res<Device>, queue: Res<Queue>,
+                     mut render_pipeline: ResMut<RenderPipeline>, mut camera_query: Query<&mut Camera>,
+                     models_query: Query<(&Model, &Transform)>,
+                     instanced_models_query: Query<(&InstancedModel, &Transform)>,
+                     mut frame_capture: ResMut<FrameCapture>, mut capture_requests: EventReader<CaptureFrame>) {
+    let camera = camera_query.iter_mut().next().unwrap();
+    let capture_paths = frame_capture.next_frame_paths(capture_requests.iter().count() > 0);
+
+    if let Some(surface) = surface {
+        let output = surface.get_current_texture().unwrap();
+        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        if capture_paths.is_empty() {
+            render_scene(&view, &device, &queue, &mut render_pipeline, &camera, models_query.iter(),
+                         instanced_models_query.iter());
+        } else {
+            // Not every backend allows reading back the surface texture, so the frame is drawn into
+            // a texture that can be, and then copied onto the surface
+            let capture_target = frame_capture.target(&device, config.width, config.height, config.format);
+            render_scene(&capture_target.target.view, &device, &queue, &mut render_pipeline, &camera,
+                         models_query.iter(), instanced_models_query.iter());
+            capture_target.copy_to(&device, &queue, &view);
+            let image = capture_target.target.read_image(&device, &queue);
+            frame_capture.save(image, capture_paths);
+        }
+        output.present();
+    } else if let Some(offscreen_target) = offscreen_target {
+        render_scene(&offscreen_target.view, &device, &queue, &mut render_pipeline, &camera, models_query.iter(),
+                     instanced_models_query.iter());
+        if !capture_paths.is_empty() {
+            frame_capture.save(offscreen_target.read_image(&device, &queue), capture_paths);
+        }
+    } else {
+        panic!("There is nothing to render to, insert a Surface or an OffscreenTarget before the first update");
+    }
 }
 
-pub(crate) fn render(surface: Res<Surface>, device: Res<Device>, queue: Res<Queue>,
-                     render_pipeline: Res<RenderPipeline>, mut camera_query: Query<&mut Camera>,
-                     models_query: Query<(&Model, &Transform)>) {
-    let output = surface.get_current_texture().unwrap();
-    let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+pub(crate) fn render_scene<'a>(view: &wgpu::TextureView, device: &Device, queue: &Queue,
+                               render_pipeline: &mut RenderPipeline, camera: &Camera,
+                               models: impl Iterator<Item = (&'a Model, &'a Transform)>,
+                               instanced_models: impl Iterator<Item = (&'a InstancedModel, &'a Transform)>) {
+    let models: Vec<_> = models.collect();
+    let transforms: Vec<WorldTransformRaw> = models.iter()
+        .map(|(_, transform)| WorldTransformRaw::from(*transform))
+        .collect();
+    let RenderPipeline { world_transforms, world_transform_bind_group_layout, .. } = render_pipeline;
+    world_transforms.write(device, queue, world_transform_bind_group_layout, &transforms);
+
+    let (raw_instances, instance_batches) = batch_instances(instanced_models);
+    render_pipeline.instances.write(device, queue, &raw_instances);
+
     let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
         label: Some("Render encoder")
     });
 
-    let camera = camera_query.iter_mut().next().unwrap();
-
     {
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
             color_attachments: &[wgpu::RenderPassColorAttachment {
-                view: &view,
+                view,
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -289,22 +341,129 @@ pub(crate) fn render(surface: Res<Surface>, device: Res<Device>, queue: Res<Queu
         render_pass.set_pipeline(&render_pipeline.wgpu_render_pipeline);
         //render_pass.set_bind_group(0, &render_pipeline.diffuse_bind_group, &[]);
         render_pass.set_bind_group(1, &camera.bind_group, &[]);
-        render_pass.set_bind_group(2, &render_pipeline.world_transform_bind_group, &[]);
+        render_pass.set_bind_group(3, &render_pipeline.lights.bind_group, &[]);
 
-        for (model, transform) in models_query.iter() {
-            queue.write_buffer(&render_pipeline.world_transform_buffer, 0, bytemuck::bytes_of(&transform.matrix));
+        for (index, (model, _)) in models.iter().enumerate() {
+            let offset = render_pipeline.world_transforms.offset(index);
+            render_pass.set_bind_group(2, &render_pipeline.world_transforms.bind_group, &[offset]);
             render_pass.draw_model(model);
         }
+
+        if !instance_batches.is_empty() {
+            render_pass.set_pipeline(&render_pipeline.wgpu_instanced_render_pipeline);
+            // Unused by the instanced vertex shader, but the shared pipeline layout requires it
+            render_pass.set_bind_group(2, &render_pipeline.world_transforms.bind_group, &[0]);
+            render_pass.set_vertex_buffer(1, render_pipeline.instances.buffer.slice(..));
+            for batch in &instance_batches {
+                render_pass.draw_model_instanced(batch.model, batch.instances.clone());
+            }
+        }
         //render_pass.set_vertex_buffer(0, render_pipeline.vertex_buffer.slice(..));
         //render_pass.set_vertex_buffer(1, render_pipeline.instance_buffer.slice(..));
         //render_pass.set_index_buffer(render_pipeline.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
         //render_pass.draw_indexed(0..render_pipeline.num_indices, 0, 0..render_pipeline.instances.len() as u32);
     }
 
-
-
-    queue.write_buffer(&camera.buffer, 0, bytemuck::bytes_of(&camera.uniform.view_projection));
+    queue.write_buffer(&camera.buffer, 0, bytemuck::bytes_of(&camera.uniform));
     // submit will accept anything that implements IntoIter
     queue.submit(std::iter::once(encoder.finish()));
-    output.present();
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::event::Events;
+    use glam::{Mat4, Quat, Vec2, Vec4};
+    use crate::app::{self, App};
+    use crate::renderer::RendererPlugin;
+    use crate::renderer::model::ModelLoadType;
+    use super::*;
+
+    const WIDTH: u32 = 256;
+    const HEIGHT: u32 = 256;
+
+    #[test]
+    fn cursor_position_follows_the_last_move() {
+        let mut world = World::new();
+        world.init_resource::<CursorPosition>();
+        world.init_resource::<Events<CursorMoved>>();
+        world.init_resource::<Events<CursorLeft>>();
+        let mut stage = SystemStage::single(update_cursor_position);
+
+        let mut cursor_moved_events = world.get_resource_mut::<Events<CursorMoved>>().unwrap();
+        cursor_moved_events.send(CursorMoved { position: Vec2::new(10.0, 20.0) });
+        cursor_moved_events.send(CursorMoved { position: Vec2::new(30.0, 40.0) });
+        stage.run(&mut world);
+        assert_eq!(world.get_resource::<CursorPosition>().unwrap().0, Some(Vec2::new(30.0, 40.0)));
+
+        world.get_resource_mut::<Events<CursorLeft>>().unwrap().send(CursorLeft);
+        stage.run(&mut world);
+        assert_eq!(world.get_resource::<CursorPosition>().unwrap().0, None);
+    }
+
+    fn to_pixel_coordinates(view_projection: Mat4, position: Vec3) -> (u32, u32) {
+        let clip = view_projection * Vec4::new(position.x, position.y, position.z, 1.0);
+        let ndc = clip / clip.w;
+        let x = (ndc.x * 0.5 + 0.5) * WIDTH as f32;
+        let y = (1.0 - (ndc.y * 0.5 + 0.5)) * HEIGHT as f32;
+        (x as u32, y as u32)
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn each_model_is_drawn_with_its_own_transform() {
+        let (_, _, device, queue) = pollster::block_on(crate::renderer::initialize_headless())
+            .expect("No wgpu adapter available");
+
+        let target = OffscreenTarget::new(&device, WIDTH, HEIGHT);
+        let (mut render_pipeline, mut camera) = create_render_pipeline(&device, &target.surface_configuration());
+        camera.position = Vec3::new(0.0, 0.0, 12.0);
+        camera.look_at(Vec3::ZERO, Vec3::Y);
+        camera.update_uniform();
+
+        let light = DirectionalLight { direction: Vec3::new(0.0, 0.0, -1.0), color: Vec3::ONE, intensity: 1.0 };
+        let RenderPipeline { lights, light_bind_group_layout, .. } = &mut render_pipeline;
+        lights.write(&device, &queue, light_bind_group_layout, &AmbientLight::default(), &gather_lights(
+            std::iter::once(&light), std::iter::empty(), std::iter::empty()));
+
+        let left = Vec3::new(-3.0, 0.0, 0.0);
+        let right = Vec3::new(3.0, 0.0, 0.0);
+        let load_cube = || Model::load_model(ModelLoadType::OBJ, "cube.obj", &device, &queue,
+                                             &render_pipeline.material_bind_group_layout).unwrap();
+        let left_cube = (load_cube(), Transform::from_mat4(Mat4::from_rotation_translation(Quat::IDENTITY, left)));
+        let right_cube = (load_cube(), Transform::from_mat4(Mat4::from_rotation_translation(Quat::IDENTITY, right)));
+
+        let models = [(&left_cube.0, &left_cube.1), (&right_cube.0, &right_cube.1)];
+        render_scene(&target.view, &device, &queue, &mut render_pipeline, &camera, models.into_iter(),
+                     std::iter::empty());
+        let image = target.read_image(&device, &queue);
+
+        let background = *image.get_pixel(0, 0);
+        for position in [left, right, Vec3::ZERO] {
+            let (x, y) = to_pixel_coordinates(camera.uniform.view_projection, position);
+            let is_background = *image.get_pixel(x, y) == background;
+            assert_eq!(is_background, position == Vec3::ZERO, "unexpected pixel at {:?}", position);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn window_resizes_reach_the_camera_and_depth_texture() {
+        let mut app = App::new();
+        app.add_plugin(RendererPlugin);
+        app::insert_headless_renderer(&mut app, WIDTH, HEIGHT).expect("No wgpu adapter available");
+        app.update();
+
+        // Like the window's surface, the render target is resized before the event is sent
+        let resized_target = OffscreenTarget::new(app.world.get_resource::<Device>().unwrap(), 400, 100);
+        app.insert_resource(resized_target.surface_configuration())
+            .insert_resource(resized_target);
+        app.world.get_resource_mut::<Events<WindowResized>>().unwrap()
+            .send(WindowResized { width: 400, height: 100 });
+        app.update();
+
+        let camera = app.world.query::<&Camera>().iter(&app.world).next().unwrap();
+        assert_eq!(camera.aspect, 4.0);
+        let depth_size = app.world.get_resource::<RenderPipeline>().unwrap().depth_texture.size;
+        assert_eq!((depth_size.width, depth_size.height), (400, 100));
+    }
+}
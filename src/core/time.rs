@@ -1,34 +1,359 @@
 use std::time::{Duration, Instant};
 use bevy_ecs::prelude::*;
+use bevy_ecs::schedule::ShouldRun;
+use crate::app::{stage, App, Plugin};
+use crate::core::timer::{self, TimerFinished};
+
+/// How much smoothing `Time::fps` gets: the weight of the newest frame in the running average
+const FPS_SMOOTHING: f32 = 0.1;
+
+/// Where `Time::update` gets the length of a frame from
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Clock {
+    /// The wall clock time since the last update
+    Real,
+    /// Exactly this long every update, for tests that need deterministic frames
+    Manual(Duration)
+}
 
 pub struct Time {
+    pub clock: Clock,
+    /// How fast game time runs compared to real time: 0.5 is slow motion, 0 stops it
+    pub time_scale: f32,
+    /// The longest a single frame may be. Longer frames, such as after stopping at a breakpoint,
+    /// are shortened to this so that the game doesn't jump ahead.
+    pub max_delta: Duration,
+    paused: bool,
     start_time: Instant,
     last_update_time: Option<Instant>,
-    pub delta_seconds: f32
+    next_delta: Option<Duration>,
+    delta_seconds: f32,
+    unscaled_delta_seconds: f32,
+    elapsed_seconds: f64,
+    unscaled_elapsed_seconds: f64,
+    frame_count: u64,
+    smoothed_delta_seconds: f32
 }
 
 impl Default for Time {
     fn default() -> Self {
         Self {
+            clock: Clock::Real,
+            time_scale: 1.0,
+            max_delta: Duration::from_millis(250),
+            paused: false,
             start_time: Instant::now(),
             last_update_time: None,
-            delta_seconds: Duration::from_secs(0).as_secs_f32()
+            next_delta: None,
+            delta_seconds: 0.0,
+            unscaled_delta_seconds: 0.0,
+            elapsed_seconds: 0.0,
+            unscaled_elapsed_seconds: 0.0,
+            frame_count: 0,
+            smoothed_delta_seconds: 0.0
         }
     }
 }
 
 impl Time {
+    pub fn with_clock(clock: Clock) -> Self {
+        Self { clock, ..Self::default() }
+    }
+
+    /// Advances by the time since the last update, or by the manual clock's step, unless
+    /// `set_next_delta` asked for a specific step
     pub fn update(&mut self) {
         let now = Instant::now();
-        if let Some(last_update_instant) = self.last_update_time {
-            self.delta_seconds = (now - last_update_instant).as_secs_f32();
+        let delta = match (self.next_delta.take(), self.clock) {
+            (Some(delta), _) => delta,
+            (None, Clock::Real) => now - self.last_update_time.unwrap_or(self.start_time),
+            (None, Clock::Manual(delta)) => delta
+        };
+        self.advance(delta, now);
+    }
+
+    /// Makes the next update advance by exactly `delta`, whatever the clock, for replaying
+    /// recorded frames
+    pub fn set_next_delta(&mut self, delta: Duration) {
+        self.next_delta = Some(delta);
+    }
+
+    fn advance(&mut self, delta: Duration, now: Instant) {
+        let unscaled_delta_seconds = delta.min(self.max_delta).as_secs_f32();
+        self.unscaled_delta_seconds = unscaled_delta_seconds;
+        self.delta_seconds = unscaled_delta_seconds * self.effective_time_scale();
+        self.unscaled_elapsed_seconds += unscaled_delta_seconds as f64;
+        self.elapsed_seconds += self.delta_seconds as f64;
+        self.smoothed_delta_seconds = if self.frame_count == 0 {
+            unscaled_delta_seconds
         } else {
-            self.delta_seconds = (now - self.start_time).as_secs_f32();
-        }
+            self.smoothed_delta_seconds + (unscaled_delta_seconds - self.smoothed_delta_seconds) * FPS_SMOOTHING
+        };
+        self.frame_count += 1;
         self.last_update_time = Some(now);
     }
+
+    fn effective_time_scale(&self) -> f32 {
+        if self.paused { 0.0 } else { self.time_scale.max(0.0) }
+    }
+
+    /// The length of the last frame in game time, after the time scale and pausing
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta_seconds
+    }
+
+    /// The length of the last frame in real time, for UI and anything else that keeps running
+    /// while the game is paused
+    pub fn unscaled_delta_seconds(&self) -> f32 {
+        self.unscaled_delta_seconds
+    }
+
+    /// Game time since the first update
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.elapsed_seconds
+    }
+
+    /// Real time since the first update, less whatever `max_delta` cut off
+    pub fn unscaled_elapsed_seconds(&self) -> f64 {
+        self.unscaled_elapsed_seconds
+    }
+
+    /// How many updates there have been
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Frames per second, averaged over recent frames so that it is readable on screen
+    pub fn fps(&self) -> f32 {
+        if self.smoothed_delta_seconds > 0.0 { 1.0 / self.smoothed_delta_seconds } else { 0.0 }
+    }
+
+    /// Stops game time without touching `time_scale`, so that resuming restores it
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
 }
 
 pub fn time_system(mut time: ResMut<Time>) {
     time.update()
-}
\ No newline at end of file
+}
+
+/// Advances `Time` at the start of every frame, ticks timers with it, and runs the "fixed_update"
+/// stage at the rate `FixedTime` asks for
+pub struct TimePlugin;
+
+impl Plugin for TimePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Time>()
+            .init_resource::<FixedTime>()
+            .add_event::<TimerFinished>()
+            .add_system_to_stage(stage::FIRST, time_system.label("time_system"))
+            .add_system_to_stage(stage::FIRST, timer::tick_timers.label("tick_timers").after("time_system"));
+        app.schedule.stage(stage::FIXED_UPDATE, |stage: &mut SystemStage| stage.set_run_criteria(fixed_timestep));
+    }
+}
+
+/// Drives the "fixed_update" stage, which runs a whole number of fixed length steps each frame to
+/// catch up with `Time`, so that physics and networking see the same step however fast frames are
+pub struct FixedTime {
+    /// Always positive and finite, otherwise a frame could never run out of steps
+    step_seconds: f32,
+    /// The most steps run in one frame. Time beyond that is dropped, so that a long frame doesn't
+    /// cause ever longer frames as the simulation tries to catch up.
+    pub max_steps_per_frame: u32,
+    accumulator: f32,
+    stepping: bool
+}
+
+impl Default for FixedTime {
+    fn default() -> Self {
+        Self::from_hz(60.0)
+    }
+}
+
+impl FixedTime {
+    /// Steps `hz` times per second. Panics unless `hz` is positive and finite.
+    pub fn from_hz(hz: f32) -> Self {
+        let step_seconds = 1.0 / hz;
+        assert_valid_step(step_seconds);
+        Self { step_seconds, max_steps_per_frame: 5, accumulator: 0.0, stepping: false }
+    }
+
+    /// The length of every step. Systems in "fixed_update" should use this instead of `Time::delta_seconds`.
+    pub fn step_seconds(&self) -> f32 {
+        self.step_seconds
+    }
+
+    /// Changes the length of every step. Panics unless `step_seconds` is positive and finite.
+    pub fn set_step_seconds(&mut self, step_seconds: f32) {
+        assert_valid_step(step_seconds);
+        self.step_seconds = step_seconds;
+    }
+
+    /// How far the frame is between the last step and the next, from 0 to 1, for interpolating
+    /// what is rendered between the last two simulated states
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step_seconds).clamp(0.0, 1.0)
+    }
+
+    fn accumulate(&mut self, delta_seconds: f32) {
+        let max_accumulated = self.step_seconds * self.max_steps_per_frame as f32;
+        self.accumulator += delta_seconds;
+        if self.accumulator > max_accumulated {
+            log::debug!("Dropping {}s of simulation to keep up", self.accumulator - max_accumulated);
+            self.accumulator = max_accumulated;
+        }
+    }
+
+    fn try_step(&mut self) -> bool {
+        if self.accumulator >= self.step_seconds {
+            self.accumulator -= self.step_seconds;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn assert_valid_step(step_seconds: f32) {
+    assert!(step_seconds.is_finite() && step_seconds > 0.0,
+            "FixedTime::step_seconds must be positive and finite, but is {}", step_seconds);
+}
+
+/// The run criteria of the "fixed_update" stage. It is checked again after each step, and only
+/// adds the frame's time before the first check of each frame.
+pub fn fixed_timestep(time: Res<Time>, mut fixed_time: ResMut<FixedTime>) -> ShouldRun {
+    if !fixed_time.stepping {
+        fixed_time.accumulate(time.delta_seconds());
+    }
+    fixed_time.stepping = fixed_time.try_step();
+    if fixed_time.stepping {
+        ShouldRun::YesAndCheckAgain
+    } else {
+        ShouldRun::No
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_advances_by_its_step() {
+        let mut time = Time::with_clock(Clock::Manual(Duration::from_millis(20)));
+        for _ in 0..3 {
+            time.update();
+        }
+        assert_eq!(time.frame_count(), 3);
+        assert_eq!(time.delta_seconds(), 0.02);
+        assert!((time.elapsed_seconds() - 0.06).abs() < 1e-6);
+        assert!((time.fps() - 50.0).abs() < 1e-3, "fps was {}", time.fps());
+    }
+
+    #[test]
+    fn time_scale_and_pause_only_slow_game_time() {
+        let mut time = Time::with_clock(Clock::Manual(Duration::from_millis(20)));
+        time.time_scale = 0.5;
+        time.update();
+        assert_eq!(time.delta_seconds(), 0.01);
+        assert_eq!(time.unscaled_delta_seconds(), 0.02);
+
+        time.pause();
+        time.update();
+        assert_eq!(time.delta_seconds(), 0.0);
+        assert_eq!(time.unscaled_delta_seconds(), 0.02);
+        assert!((time.elapsed_seconds() - 0.01).abs() < 1e-6);
+        assert!((time.unscaled_elapsed_seconds() - 0.04).abs() < 1e-6);
+
+        time.resume();
+        time.update();
+        assert_eq!(time.delta_seconds(), 0.01);
+    }
+
+    #[test]
+    fn a_set_delta_replaces_the_clock_once() {
+        let mut time = Time::with_clock(Clock::Manual(Duration::from_millis(20)));
+        time.set_next_delta(Duration::from_millis(5));
+        time.update();
+        assert_eq!(time.delta_seconds(), 0.005);
+        time.update();
+        assert_eq!(time.delta_seconds(), 0.02);
+    }
+
+    #[test]
+    fn long_frames_are_shortened_to_the_max_delta() {
+        let mut time = Time::with_clock(Clock::Manual(Duration::from_secs(10)));
+        time.update();
+        assert_eq!(time.unscaled_delta_seconds(), 0.25);
+        assert_eq!(time.delta_seconds(), 0.25);
+    }
+
+    #[derive(Default)]
+    struct Steps(u32);
+
+    fn count_steps(mut steps: ResMut<Steps>) {
+        steps.0 += 1;
+    }
+
+    /// Runs a frame of `delta_seconds` and returns how many fixed steps it took
+    fn run_frame(world: &mut World, stage: &mut SystemStage, delta_seconds: f32) -> u32 {
+        world.get_resource_mut::<Time>().unwrap().delta_seconds = delta_seconds;
+        world.insert_resource(Steps(0));
+        stage.run(world);
+        world.get_resource::<Steps>().unwrap().0
+    }
+
+    fn fixed_world() -> (World, SystemStage) {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<FixedTime>();
+        let stage = SystemStage::single(count_steps).with_run_criteria(fixed_timestep);
+        (world, stage)
+    }
+
+    #[test]
+    fn frames_run_whole_steps_and_carry_the_rest() {
+        let (mut world, mut stage) = fixed_world();
+        assert_eq!(run_frame(&mut world, &mut stage, 0.04), 2);
+        let alpha = world.get_resource::<FixedTime>().unwrap().alpha();
+        assert!((alpha - 0.4).abs() < 1e-3, "alpha was {}", alpha);
+
+        assert_eq!(run_frame(&mut world, &mut stage, 0.005), 0);
+        assert_eq!(run_frame(&mut world, &mut stage, 0.006), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "positive and finite")]
+    fn zero_hz_is_rejected() {
+        FixedTime::from_hz(0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "positive and finite")]
+    fn invalid_steps_set_by_hand_are_rejected() {
+        FixedTime::default().set_step_seconds(-0.01);
+    }
+
+    #[test]
+    fn steps_set_by_hand_are_used() {
+        let (mut world, mut stage) = fixed_world();
+        world.get_resource_mut::<FixedTime>().unwrap().set_step_seconds(0.01);
+        assert_eq!(world.get_resource::<FixedTime>().unwrap().step_seconds(), 0.01);
+        assert_eq!(run_frame(&mut world, &mut stage, 0.035), 3);
+    }
+
+    #[test]
+    fn long_frames_are_clamped() {
+        let (mut world, mut stage) = fixed_world();
+        assert_eq!(run_frame(&mut world, &mut stage, 2.0), 5);
+        assert_eq!(run_frame(&mut world, &mut stage, 0.0), 0);
+    }
+}
@@ -0,0 +1,278 @@
+//! Timers and stopwatches for cooldowns, delays and anything else measured in game time. Both work
+//! as components or as resources, and are ticked by `tick_timers` at the start of every frame
+//! with the scaled time step, so they stop while the game is paused.
+
+use bevy_ecs::prelude::*;
+use crate::core::time::Time;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerMode {
+    /// Finishes once and stays finished until reset
+    Once,
+    /// Starts over every time it finishes
+    Repeating
+}
+
+/// Counts down a duration of game time
+#[derive(Component, Clone, Debug)]
+pub struct Timer {
+    duration_seconds: f32,
+    mode: TimerMode,
+    elapsed_seconds: f32,
+    paused: bool,
+    finished: bool,
+    times_finished_this_tick: u32
+}
+
+impl Timer {
+    pub fn new(duration_seconds: f32, mode: TimerMode) -> Self {
+        Self {
+            duration_seconds: duration_seconds.max(0.0),
+            mode,
+            elapsed_seconds: 0.0,
+            paused: false,
+            finished: false,
+            times_finished_this_tick: 0
+        }
+    }
+
+    pub fn once(duration_seconds: f32) -> Self {
+        Self::new(duration_seconds, TimerMode::Once)
+    }
+
+    pub fn repeating(duration_seconds: f32) -> Self {
+        Self::new(duration_seconds, TimerMode::Repeating)
+    }
+
+    /// Advances the timer. A repeating timer keeps whatever time passed beyond the end, so that it
+    /// doesn't drift, and can finish more than once in a long tick.
+    pub fn tick(&mut self, delta_seconds: f32) -> &Self {
+        self.times_finished_this_tick = 0;
+        if self.paused || (self.mode == TimerMode::Once && self.finished) {
+            return self;
+        }
+
+        self.elapsed_seconds += delta_seconds;
+        self.finished = self.elapsed_seconds >= self.duration_seconds;
+        if self.finished {
+            match self.mode {
+                TimerMode::Once => {
+                    self.times_finished_this_tick = 1;
+                    self.elapsed_seconds = self.duration_seconds;
+                },
+                TimerMode::Repeating if self.duration_seconds > 0.0 => {
+                    self.times_finished_this_tick = (self.elapsed_seconds / self.duration_seconds) as u32;
+                    self.elapsed_seconds %= self.duration_seconds;
+                },
+                TimerMode::Repeating => {
+                    self.times_finished_this_tick = 1;
+                    self.elapsed_seconds = 0.0;
+                }
+            }
+        }
+        self
+    }
+
+    /// Whether a one-shot timer has run out, or a repeating timer started over during the last tick
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Whether the timer finished during the last tick
+    pub fn just_finished(&self) -> bool {
+        self.times_finished_this_tick > 0
+    }
+
+    /// How many times the timer finished during the last tick, which is more than one only for a
+    /// repeating timer shorter than the tick
+    pub fn times_finished_this_tick(&self) -> u32 {
+        self.times_finished_this_tick
+    }
+
+    pub fn mode(&self) -> TimerMode {
+        self.mode
+    }
+
+    pub fn duration_seconds(&self) -> f32 {
+        self.duration_seconds
+    }
+
+    /// Changes the duration without restarting the timer
+    pub fn set_duration_seconds(&mut self, duration_seconds: f32) {
+        self.duration_seconds = duration_seconds.max(0.0);
+    }
+
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed_seconds
+    }
+
+    pub fn remaining_seconds(&self) -> f32 {
+        (self.duration_seconds - self.elapsed_seconds).max(0.0)
+    }
+
+    /// How far through its duration the timer is, from 0 to 1
+    pub fn fraction(&self) -> f32 {
+        if self.duration_seconds > 0.0 {
+            (self.elapsed_seconds / self.duration_seconds).min(1.0)
+        } else {
+            1.0
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn unpause(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Starts the timer over, keeping its duration, mode and whether it is paused
+    pub fn reset(&mut self) {
+        self.elapsed_seconds = 0.0;
+        self.finished = false;
+        self.times_finished_this_tick = 0;
+    }
+}
+
+/// Counts up game time
+#[derive(Component, Clone, Debug, Default)]
+pub struct Stopwatch {
+    elapsed_seconds: f32,
+    paused: bool
+}
+
+impl Stopwatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tick(&mut self, delta_seconds: f32) -> &Self {
+        if !self.paused {
+            self.elapsed_seconds += delta_seconds;
+        }
+        self
+    }
+
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed_seconds
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn unpause(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn reset(&mut self) {
+        self.elapsed_seconds = 0.0;
+    }
+}
+
+/// Sent once for every tick in which a timer finished
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimerFinished {
+    /// The entity the timer is a component of, or `None` for the `Timer` resource
+    pub entity: Option<Entity>
+}
+
+/// Ticks every `Timer` and `Stopwatch` component, and the `Timer` and `Stopwatch` resources if
+/// there are any
+pub fn tick_timers(time: Res<Time>, mut timers: Query<(Entity, &mut Timer)>,
+                   mut stopwatches: Query<&mut Stopwatch>, timer_resource: Option<ResMut<Timer>>,
+                   stopwatch_resource: Option<ResMut<Stopwatch>>,
+                   mut finished_events: EventWriter<TimerFinished>) {
+    let delta_seconds = time.delta_seconds();
+    for (entity, mut timer) in timers.iter_mut() {
+        if timer.tick(delta_seconds).just_finished() {
+            finished_events.send(TimerFinished { entity: Some(entity) });
+        }
+    }
+    for mut stopwatch in stopwatches.iter_mut() {
+        stopwatch.tick(delta_seconds);
+    }
+
+    if let Some(mut timer) = timer_resource {
+        if timer.tick(delta_seconds).just_finished() {
+            finished_events.send(TimerFinished { entity: None });
+        }
+    }
+    if let Some(mut stopwatch) = stopwatch_resource {
+        stopwatch.tick(delta_seconds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bevy_ecs::event::Events;
+    use crate::core::time::Clock;
+    use super::*;
+
+    #[test]
+    fn one_shot_timers_finish_once() {
+        let mut timer = Timer::once(1.0);
+        assert!(!timer.tick(0.6).finished());
+        assert!(timer.tick(0.6).just_finished());
+        assert_eq!(timer.elapsed_seconds(), 1.0);
+        assert!(!timer.tick(0.6).just_finished());
+        assert!(timer.finished());
+
+        timer.reset();
+        assert!(!timer.finished());
+        assert_eq!(timer.remaining_seconds(), 1.0);
+    }
+
+    #[test]
+    fn repeating_timers_keep_the_overshoot() {
+        let mut timer = Timer::repeating(0.5);
+        assert_eq!(timer.tick(1.2).times_finished_this_tick(), 2);
+        assert!((timer.elapsed_seconds() - 0.2).abs() < 1e-6);
+        assert!(!timer.tick(0.2).finished());
+        assert!(timer.tick(0.1).just_finished());
+    }
+
+    #[test]
+    fn paused_timers_and_stopwatches_stand_still() {
+        let mut timer = Timer::once(1.0);
+        timer.pause();
+        assert!(!timer.tick(2.0).finished());
+        assert_eq!(timer.elapsed_seconds(), 0.0);
+
+        let mut stopwatch = Stopwatch::new();
+        stopwatch.tick(0.5);
+        stopwatch.pause();
+        stopwatch.tick(0.5);
+        assert_eq!(stopwatch.elapsed_seconds(), 0.5);
+    }
+
+    #[test]
+    fn finished_timers_send_events() {
+        let mut world = World::new();
+        world.insert_resource(Time::with_clock(Clock::Manual(Duration::from_millis(100))));
+        world.init_resource::<Events<TimerFinished>>();
+        world.insert_resource(Timer::once(0.15));
+        let entity = world.spawn().insert(Timer::repeating(0.1)).insert(Stopwatch::new()).id();
+        let mut stage = SystemStage::single(tick_timers);
+
+        let mut run_frame = |world: &mut World| {
+            world.get_resource_mut::<Time>().unwrap().update();
+            stage.run(world);
+            let mut events = world.get_resource_mut::<Events<TimerFinished>>().unwrap();
+            events.drain().collect::<Vec<_>>()
+        };
+        assert_eq!(run_frame(&mut world), [TimerFinished { entity: Some(entity) }]);
+        assert_eq!(run_frame(&mut world), [TimerFinished { entity: Some(entity) }, TimerFinished { entity: None }]);
+        assert!((world.get::<Stopwatch>(entity).unwrap().elapsed_seconds() - 0.2).abs() < 1e-6);
+    }
+}
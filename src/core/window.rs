@@ -0,0 +1,25 @@
+use glam::Vec2;
+
+/// Sent when the drawable area of the window changes size, after the surface has been reconfigured.
+/// Never sent for a zero sized window, such as when it is minimized.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WindowResized {
+    pub width: u32,
+    pub height: u32
+}
+
+/// Sent when the cursor moves over the window, with its position in physical pixels from the
+/// window's top left corner
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CursorMoved {
+    pub position: Vec2
+}
+
+/// Sent when the cursor leaves the window
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CursorLeft;
+
+/// Where the cursor was last seen over the window, in the same coordinates as `CursorMoved`.
+/// `None` while it is outside the window.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CursorPosition(pub Option<Vec2>);
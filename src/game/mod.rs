@@ -1,18 +1,39 @@
 use std::f32::consts::PI;
+use std::sync::Arc;
 use bevy_ecs::prelude::*;
 use glam::{Mat4, Quat, Vec3};
 use wgpu::{Device, Queue};
+use crate::app::{App, Plugin};
+use crate::core::camera_controller::{FlyCameraController, OrbitCameraController};
+use crate::renderer::camera::Camera;
+use crate::renderer::instance::InstancedModel;
+use crate::renderer::light::{DirectionalLight, PointLight};
 use crate::renderer::model::{Model, ModelLoadType};
 use crate::renderer::pipeline::RenderPipeline;
 use crate::renderer::Transform;
 
-pub fn start(mut commands: Commands, device: Res<Device>, queue: Res<Queue>, render_pipeline: Res<RenderPipeline>) {
+pub struct GamePlugin;
+
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(start);
+    }
+}
+
+pub fn start(mut commands: Commands, device: Res<Device>, queue: Res<Queue>, render_pipeline: Res<RenderPipeline>,
+             cameras: Query<(Entity, &Camera)>) {
+    for (entity, camera) in cameras.iter() {
+        commands.entity(entity)
+            .insert(FlyCameraController::looking_along(camera.forward()))
+            .insert(OrbitCameraController::around(Vec3::ZERO, camera.position));
+    }
+
     let model = Model::load_model(
         ModelLoadType::OBJ,
         "cube.obj",
         &device,
         &queue,
-        &render_pipeline.diffuse_bind_group_layout
+        &render_pipeline.material_bind_group_layout
     ).expect("Unable to load cube model");
 
     commands.spawn().insert(model).insert(
@@ -22,4 +43,41 @@ pub fn start(mut commands: Commands, device: Res<Device>, queue: Res<Queue>, ren
                 Vec3::new(3.0, 1.0, -3.0))
         )
     );
-}
\ No newline at end of file
+
+    commands.spawn().insert(DirectionalLight {
+        direction: Vec3::new(-0.5, -1.0, -0.3),
+        color: Vec3::ONE,
+        intensity: 0.8
+    });
+
+    commands.spawn().insert(PointLight {
+        position: Vec3::new(0.0, 2.0, -10.0),
+        color: Vec3::new(1.0, 0.6, 0.3),
+        intensity: 20.0,
+        range: 15.0
+    });
+
+    let instanced_cube = InstancedModel::new(Arc::new(
+        Model::load_model(
+            ModelLoadType::OBJ,
+            "cube.obj",
+            &device,
+            &queue,
+            &render_pipeline.material_bind_group_layout
+        ).expect("Unable to load cube model")
+    ));
+
+    // A field of cubes that all share one model and are drawn with a single instanced draw call
+    for x in -5..5 {
+        for z in -5..5 {
+            commands.spawn().insert(instanced_cube.clone()).insert(
+                Transform::from_rotation_translation(
+                    Quat::from_rotation_y(PI * 0.1 * (x + z) as f32),
+                    x as f32 * 4.0,
+                    -4.0,
+                    z as f32 * 4.0 - 20.0
+                )
+            );
+        }
+    }
+}
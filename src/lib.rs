@@ -0,0 +1,4 @@
+pub mod app;
+pub mod renderer;
+pub mod core;
+pub mod game;
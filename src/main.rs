@@ -1,8 +1,15 @@
-mod app;
-mod renderer;
-mod core;
-mod game;
+use cosmico::{app, core, game};
 
 fn main() {
-    pollster::block_on(app::run());
+    let input_mode = match core::recording::InputMode::from_args(std::env::args().skip(1)) {
+        Ok(input_mode) => input_mode,
+        Err(error) => {
+            eprintln!("{}\nUsage: cosmico [--record <file> | --replay <file>]", error);
+            std::process::exit(2);
+        }
+    };
+    let mut app = app::App::new();
+    app.add_plugin(app::EnginePlugin)
+        .add_plugin(game::GamePlugin);
+    pollster::block_on(app::run(app, input_mode));
 }
@@ -1,26 +1,24 @@
-use glam::{Mat4, Vec3};
+use glam::{Mat3, Mat4, Quat, Vec3, Vec4};
 use bytemuck::{ Pod, Zeroable };
 use bevy_ecs::prelude::*;
-use bevy_input::keyboard::{KeyCode};
 
 #[derive(Component)]
 pub struct Camera {
-    pub eye: Vec3,
-    pub target: Vec3,
-    pub up: Vec3,
+    pub position: Vec3,
+    /// Rotates from camera space, where the camera looks down -Z with +Y up, to world space
+    pub orientation: Quat,
     pub aspect: f32,
     pub fov_y: f32,
     pub z_near: f32,
     pub z_far: f32,
     pub uniform: CameraUniform,
-    pub speed: f32,
     pub buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup
 }
 
 impl Camera {
     fn build_view_projection_matrix(&self) -> Mat4 {
-        let view = Mat4::look_at_rh(self.eye, self.target, self.up);
+        let view = Mat4::from_rotation_translation(self.orientation, self.position).inverse();
         let projection = Mat4::perspective_rh(
             self.fov_y,
             self.aspect,
@@ -28,43 +26,79 @@ impl Camera {
             self.z_far
         );
 
-        return projection * view;
+        projection * view
     }
 
-    pub fn update(&mut self, key_inputs: &Vec<KeyCode>, delta_time: f32) {
-        for key_code in key_inputs {
-            let forward = self.target - self.eye;
-            let forward_normalized = forward.normalize();
-            if *key_code == KeyCode::W {
-                self.eye += forward_normalized * self.speed * delta_time
-            }
-            if *key_code == KeyCode::S {
-                self.eye -= forward_normalized * self.speed * delta_time
-            }
-
-            let forward = self.target - self.eye;
-            let right = forward.normalize().cross(self.up);
-            if *key_code == KeyCode::A {
-                self.eye -= right * self.speed * delta_time;
-            }
-            if *key_code == KeyCode::D {
-                self.eye += right * self.speed * delta_time;
-            }
-        }
+    pub fn forward(&self) -> Vec3 {
+        self.orientation * -Vec3::Z
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.orientation * Vec3::X
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.orientation * Vec3::Y
+    }
+
+    /// Turns the camera to face `target` from where it is
+    pub fn look_at(&mut self, target: Vec3, up: Vec3) {
+        self.orientation = orientation_looking_at(self.position, target, up);
+    }
+
+    /// Matches the projection to a new viewport size
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.aspect = width as f32 / height as f32;
+        self.update_uniform();
+    }
+
+    /// Recomputes the shader data after the position, orientation or projection changed
+    pub fn update_uniform(&mut self) {
         self.uniform.view_projection = self.build_view_projection_matrix();
+        self.uniform.view_position = self.position.extend(1.0);
     }
 }
 
+/// The orientation of a camera at `eye` that faces `target`
+pub fn orientation_looking_at(eye: Vec3, target: Vec3, up: Vec3) -> Quat {
+    let view = Mat4::look_at_rh(eye, target, up);
+    Quat::from_mat3(&Mat3::from_mat4(view)).inverse().normalize()
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
 pub struct CameraUniform {
-    pub view_projection: Mat4
+    pub view_projection: Mat4,
+    pub view_position: Vec4
 }
 
 impl CameraUniform {
     pub fn new() -> Self {
         Self {
-            view_projection: Mat4::IDENTITY
+            view_projection: Mat4::IDENTITY,
+            view_position: Vec4::ZERO
         }
     }
 }
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looking_at_a_target_points_forward_at_it() {
+        let eye = Vec3::new(0.0, 1.0, 2.0);
+        let orientation = orientation_looking_at(eye, Vec3::ZERO, Vec3::Y);
+
+        let forward = orientation * -Vec3::Z;
+        assert!(forward.abs_diff_eq((Vec3::ZERO - eye).normalize(), 1e-5), "forward was {:?}", forward);
+        let right = orientation * Vec3::X;
+        assert!(right.abs_diff_eq(Vec3::X, 1e-5), "right was {:?}", right);
+    }
+}
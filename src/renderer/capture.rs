@@ -0,0 +1,282 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+use image::RgbaImage;
+use wgpu::{Device, Queue};
+use crate::renderer::offscreen::OffscreenTarget;
+
+/// Asks the renderer to save the next frame it draws as a timestamped PNG
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CaptureFrame;
+
+/// How many captured frames may wait to be written before rendering waits for the writer
+const MAX_QUEUED_FRAMES: usize = 4;
+
+/// Where captured frames are written, and whether every frame is being recorded
+pub struct FrameCapture {
+    pub directory: PathBuf,
+    sequence: Option<FrameSequence>,
+    /// Where frames are drawn while capturing from a window, since surface textures can't be read back
+    target: Option<CaptureTarget>,
+    writer: Option<FrameWriter>
+}
+
+/// A recording of every rendered frame as a numbered PNG sequence
+struct FrameSequence {
+    directory: PathBuf,
+    next_frame: u32
+}
+
+impl Default for FrameCapture {
+    fn default() -> Self {
+        Self::new("screenshots")
+    }
+}
+
+impl FrameCapture {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self { directory: directory.into(), sequence: None, target: None, writer: None }
+    }
+
+    /// Starts writing every frame to `<directory>/<name>/frame_000000.png` and onwards, for example
+    /// to make trailer footage. Replaces any sequence already being recorded.
+    pub fn start_sequence(&mut self, name: &str) {
+        self.sequence = Some(FrameSequence { directory: self.directory.join(name), next_frame: 0 });
+    }
+
+    /// Stops recording and waits until every captured frame has been written
+    pub fn stop_sequence(&mut self) {
+        self.sequence = None;
+        if let Some(writer) = self.writer.take() {
+            writer.finish();
+        }
+    }
+
+    pub fn is_recording_sequence(&self) -> bool {
+        self.sequence.is_some()
+    }
+
+    /// The paths the current frame should be written to: one for a requested screenshot, and one
+    /// for the next frame of the sequence if one is being recorded
+    pub fn next_frame_paths(&mut self, screenshot_requested: bool) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        if screenshot_requested {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            paths.push(self.directory.join(
+                format!("screenshot_{}_{:03}.png", timestamp.as_secs(), timestamp.subsec_millis())));
+        }
+        if let Some(sequence) = &mut self.sequence {
+            paths.push(sequence.directory.join(format!("frame_{:06}.png", sequence.next_frame)));
+            sequence.next_frame += 1;
+        }
+        paths
+    }
+
+    /// A target matching the surface that captured frames can be drawn to, recreated whenever the
+    /// surface changes size or format
+    pub fn target(&mut self, device: &Device, width: u32, height: u32, format: wgpu::TextureFormat)
+        -> &CaptureTarget {
+        let is_stale = !self.target.as_ref().is_some_and(|capture_target| {
+            let target = &capture_target.target;
+            target.width == width && target.height == height && target.format == format
+        });
+        if is_stale {
+            self.target = Some(CaptureTarget::new(device, width, height, format));
+        }
+        self.target.as_ref().unwrap()
+    }
+
+    /// Queues the frame to be written to every path on the writer thread, so encoding doesn't
+    /// stall rendering. Waits if the writer has fallen `MAX_QUEUED_FRAMES` behind.
+    pub fn save(&mut self, image: RgbaImage, paths: Vec<PathBuf>) {
+        self.writer.get_or_insert_with(FrameWriter::spawn).write(image, paths);
+    }
+}
+
+impl Drop for FrameCapture {
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            writer.finish();
+        }
+    }
+}
+
+/// An offscreen target that frames are drawn to while capturing, and then drawn onto the surface
+/// from. Not every backend allows copying to or from surface textures, so the copy is a draw.
+pub struct CaptureTarget {
+    pub target: OffscreenTarget,
+    blit_pipeline: wgpu::RenderPipeline,
+    blit_bind_group: wgpu::BindGroup
+}
+
+impl CaptureTarget {
+    fn new(device: &Device, width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        let target = OffscreenTarget::with_format(device, width, height, format);
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Blit Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/blit.wgsl").into()),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Blit Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false
+                },
+                count: None
+            }]
+        });
+        let blit_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Blit Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&target.view)
+            }]
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Blit Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[]
+        });
+        let blit_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Blit Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vertex_shader_main",
+                buffers: &[]
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fragment_shader_main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL
+                }]
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None
+        });
+
+        Self { target, blit_pipeline, blit_bind_group }
+    }
+
+    /// Draws the captured frame onto `view`, which must have the size and format of the target
+    pub fn copy_to(&self, device: &Device, queue: &Queue, view: &wgpu::TextureView) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Blit Encoder")
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Blit Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true
+                    }
+                }],
+                depth_stencil_attachment: None
+            });
+            render_pass.set_pipeline(&self.blit_pipeline);
+            render_pass.set_bind_group(0, &self.blit_bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
+
+/// A thread that writes captured frames in the order they were rendered
+struct FrameWriter {
+    sender: SyncSender<(RgbaImage, Vec<PathBuf>)>,
+    thread: JoinHandle<()>
+}
+
+impl FrameWriter {
+    fn spawn() -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<(RgbaImage, Vec<PathBuf>)>(MAX_QUEUED_FRAMES);
+        let thread = std::thread::Builder::new()
+            .name("frame writer".to_string())
+            .spawn(move || {
+                for (image, paths) in receiver {
+                    for path in paths {
+                        if let Err(error) = save_png(&image, &path) {
+                            log::error!("Unable to save captured frame {}: {}", path.display(), error);
+                        }
+                    }
+                }
+            })
+            .expect("Unable to start the frame writer thread");
+        Self { sender, thread }
+    }
+
+    fn write(&self, image: RgbaImage, paths: Vec<PathBuf>) {
+        // The receiver only goes away if the thread panicked, which has been logged already
+        let _ = self.sender.send((image, paths));
+    }
+
+    /// Waits until every queued frame has been written
+    fn finish(self) {
+        drop(self.sender);
+        if self.thread.join().is_err() {
+            log::error!("The frame writer thread panicked");
+        }
+    }
+}
+
+fn save_png(image: &RgbaImage, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+    image.save(path)?;
+    log::info!("Saved captured frame {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequences_are_numbered_per_frame() {
+        let mut capture = FrameCapture::new("captures");
+        assert!(capture.next_frame_paths(false).is_empty());
+
+        capture.start_sequence("trailer");
+        assert_eq!(capture.next_frame_paths(false), [Path::new("captures/trailer/frame_000000.png")]);
+
+        let paths = capture.next_frame_paths(true);
+        assert_eq!(paths.len(), 2);
+        assert!(paths[0].starts_with("captures"));
+        assert!(paths[0].file_name().unwrap().to_str().unwrap().starts_with("screenshot_"));
+        assert_eq!(paths[1], Path::new("captures/trailer/frame_000001.png"));
+
+        capture.stop_sequence();
+        assert!(capture.next_frame_paths(false).is_empty());
+    }
+
+    #[test]
+    fn stopping_a_sequence_waits_for_every_frame() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("capture_test");
+        let _ = std::fs::remove_dir_all(&directory);
+        let mut capture = FrameCapture::new(&directory);
+        capture.start_sequence("sequence");
+        for _ in 0..MAX_QUEUED_FRAMES * 2 {
+            let paths = capture.next_frame_paths(false);
+            capture.save(RgbaImage::new(8, 8), paths);
+        }
+        capture.stop_sequence();
+
+        let written = std::fs::read_dir(directory.join("sequence")).unwrap().count();
+        assert_eq!(written, MAX_QUEUED_FRAMES * 2);
+    }
+}
@@ -1,45 +1,48 @@
-use std::f32::consts::PI;
-use glam::{Mat4, Quat, Vec3};
-use wgpu::{vertex_attr_array, VertexAttribute};
+use std::collections::HashMap;
+use std::sync::Arc;
+use glam::{Mat4, Vec4};
+use wgpu::{vertex_attr_array, BufferUsages, Device, Queue, VertexAttribute};
 use bytemuck::{Pod, Zeroable};
+use bevy_ecs::prelude::*;
+use crate::renderer::model::Model;
+use crate::renderer::pipeline::padded_columns;
+use crate::renderer::Transform;
 
-pub struct Instance {
-    position: Vec3,
-    rotation: Quat
+/// Marks an entity as one instance of a shared model. Every entity holding the same `Arc<Model>`
+/// is gathered into the per-frame instance buffer and drawn with a single instanced draw call.
+#[derive(Component, Clone)]
+pub struct InstancedModel {
+    pub model: Arc<Model>
 }
 
-impl Instance {
-    /// Used to convert an instance into a 4x4 matrix to be used in shader code
-    pub fn to_raw_instance(&self) -> InstanceRaw {
-        InstanceRaw {
-            matrix: Mat4::from_rotation_translation(self.rotation, self.position)
-        }
-    }
-
-    pub fn raw_test_instances() -> Vec<InstanceRaw> {
-        let test_instances: [Instance; 6] = [
-            Instance { position: Vec3::new(0.0, 0.0, 2.0), rotation: Quat::from_rotation_x(PI * 1.0) },
-            Instance { position: Vec3::new(2.0, 0.0, 0.0), rotation: Quat::from_rotation_x(PI * 0.5) },
-            Instance { position: Vec3::new(-2.0, 0.0, 0.0), rotation: Quat::from_rotation_y(PI * 1.0) },
-            Instance { position: Vec3::new(0.0, 0.0, -2.0), rotation: Quat::from_rotation_y(PI * 0.5) },
-            Instance { position: Vec3::new(2.0, 0.0, 2.0), rotation: Quat::from_rotation_z(PI * 1.0) },
-            Instance { position: Vec3::new(-2.0, 0.0, -2.0), rotation: Quat::from_rotation_z(PI * 0.5) },
-        ];
-        test_instances.iter().map(Instance::to_raw_instance).collect()
+impl InstancedModel {
+    pub fn new(model: Arc<Model>) -> Self {
+        Self { model }
     }
 }
+
 #[repr(C)]
 #[derive(Copy, Clone, Pod, Zeroable)]
 pub struct InstanceRaw {
-    matrix: Mat4
+    matrix: Mat4,
+    normal_matrix: [Vec4; 3]
+}
+
+impl From<&Transform> for InstanceRaw {
+    fn from(transform: &Transform) -> Self {
+        Self { matrix: transform.matrix, normal_matrix: padded_columns(transform.normal_matrix()) }
+    }
 }
 
 impl InstanceRaw {
-    const VERTEX_BUFFER_ATTRIBUTES: [VertexAttribute; 4] = vertex_attr_array![
+    const VERTEX_BUFFER_ATTRIBUTES: [VertexAttribute; 7] = vertex_attr_array![
         5 => Float32x4,
         6 => Float32x4,
         7 => Float32x4,
-        8 => Float32x4
+        8 => Float32x4,
+        9 => Float32x4,
+        10 => Float32x4,
+        11 => Float32x4
     ];
 
     pub fn buffer_layout_description<'a>() -> wgpu::VertexBufferLayout<'a> {
@@ -49,4 +52,134 @@ impl InstanceRaw {
             attributes: &Self::VERTEX_BUFFER_ATTRIBUTES
         }
     }
-}
\ No newline at end of file
+}
+
+/// A group of instances of one model, stored contiguously in the instance buffer
+pub struct InstanceBatch<'a> {
+    pub model: &'a Model,
+    pub instances: std::ops::Range<u32>
+}
+
+/// Groups instanced entities by the model they share, returning the instance data for every batch
+/// laid out back to back alongside the range each batch occupies
+pub fn batch_instances<'a>(instanced_models: impl Iterator<Item = (&'a InstancedModel, &'a Transform)>)
+    -> (Vec<InstanceRaw>, Vec<InstanceBatch<'a>>) {
+    let mut batch_indices: HashMap<*const Model, usize> = HashMap::new();
+    let mut grouped: Vec<(&Model, Vec<InstanceRaw>)> = Vec::new();
+    for (instanced_model, transform) in instanced_models {
+        let index = *batch_indices.entry(Arc::as_ptr(&instanced_model.model)).or_insert_with(|| {
+            grouped.push((&instanced_model.model, Vec::new()));
+            grouped.len() - 1
+        });
+        grouped[index].1.push(InstanceRaw::from(transform));
+    }
+
+    let mut raw_instances = Vec::new();
+    let mut batches = Vec::with_capacity(grouped.len());
+    for (model, instances) in grouped {
+        let start = raw_instances.len() as u32;
+        raw_instances.extend(instances);
+        batches.push(InstanceBatch { model, instances: start..raw_instances.len() as u32 });
+    }
+    (raw_instances, batches)
+}
+
+/// A vertex buffer holding every instance drawn this frame, grown as needed
+pub struct InstanceBuffer {
+    pub buffer: wgpu::Buffer,
+    capacity: usize
+}
+
+impl InstanceBuffer {
+    const INITIAL_CAPACITY: usize = 1024;
+
+    pub fn new(device: &Device) -> Self {
+        Self::with_capacity(device, Self::INITIAL_CAPACITY)
+    }
+
+    fn with_capacity(device: &Device, capacity: usize) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        Self { buffer, capacity }
+    }
+
+    pub fn write(&mut self, device: &Device, queue: &Queue, instances: &[InstanceRaw]) {
+        if instances.len() > self.capacity {
+            *self = Self::with_capacity(device, instances.len().next_power_of_two());
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(instances));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Quat;
+    use super::*;
+
+    fn empty_model() -> Arc<Model> {
+        Arc::new(Model { meshes: Vec::new(), materials: Vec::new() })
+    }
+
+    fn at(x: f32) -> Transform {
+        Transform::from_rotation_translation(Quat::IDENTITY, x, 0.0, 0.0)
+    }
+
+    #[test]
+    fn instances_are_grouped_by_shared_model() {
+        let cube = InstancedModel::new(empty_model());
+        let sphere = InstancedModel::new(empty_model());
+        let entities = [(cube.clone(), at(0.0)), (sphere.clone(), at(1.0)), (cube.clone(), at(2.0))];
+
+        let (raw_instances, batches) = batch_instances(entities.iter().map(|(model, transform)| (model, transform)));
+        assert_eq!(batches.len(), 2);
+        assert!(std::ptr::eq(batches[0].model, &*cube.model));
+        assert_eq!(batches[0].instances, 0..2);
+        assert!(std::ptr::eq(batches[1].model, &*sphere.model));
+        assert_eq!(batches[1].instances, 2..3);
+
+        let x: Vec<f32> = raw_instances.iter().map(|instance| instance.matrix.w_axis.x).collect();
+        assert_eq!(x, [0.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn instance_transforms_fill_shader_locations_5_to_11() {
+        let layout = InstanceRaw::buffer_layout_description();
+        assert_eq!(layout.array_stride, 112);
+        assert_eq!(layout.step_mode, wgpu::VertexStepMode::Instance);
+
+        let attributes: Vec<_> = layout.attributes.iter()
+            .map(|attribute| (attribute.shader_location, attribute.offset, attribute.format))
+            .collect();
+        assert_eq!(attributes, [
+            (5, 0, wgpu::VertexFormat::Float32x4),
+            (6, 16, wgpu::VertexFormat::Float32x4),
+            (7, 32, wgpu::VertexFormat::Float32x4),
+            (8, 48, wgpu::VertexFormat::Float32x4),
+            (9, 64, wgpu::VertexFormat::Float32x4),
+            (10, 80, wgpu::VertexFormat::Float32x4),
+            (11, 96, wgpu::VertexFormat::Float32x4)
+        ]);
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn instance_buffer_grows_past_its_initial_capacity() {
+        let (_, _, device, queue) = pollster::block_on(crate::renderer::initialize_headless())
+            .expect("No wgpu adapter available");
+        let mut instance_buffer = InstanceBuffer::new(&device);
+
+        let instances = vec![InstanceRaw::from(&at(1.0)); InstanceBuffer::INITIAL_CAPACITY + 1];
+        instance_buffer.write(&device, &queue, &instances);
+        assert_eq!(instance_buffer.capacity, 2 * InstanceBuffer::INITIAL_CAPACITY);
+
+        // Writing fewer instances keeps the larger buffer
+        instance_buffer.write(&device, &queue, &instances[..1]);
+        assert_eq!(instance_buffer.capacity, 2 * InstanceBuffer::INITIAL_CAPACITY);
+        device.poll(wgpu::Maintain::Wait);
+    }
+}
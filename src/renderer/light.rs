@@ -0,0 +1,241 @@
+use std::num::NonZeroU64;
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec3, Vec4};
+use wgpu::{BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindingResource, BufferBinding, BufferUsages, Device, Queue};
+use bevy_ecs::prelude::*;
+
+/// Light shining uniformly in one direction, like the sun
+#[derive(Component, Clone, Copy, Debug)]
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub intensity: f32
+}
+
+/// Light radiating in every direction from a point, fading out towards `range`
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PointLight {
+    pub position: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub range: f32
+}
+
+/// Light radiating from a point in a cone. The cone is fully lit inside `inner_angle` and fades
+/// to nothing at `outer_angle`, both measured in radians from `direction`
+#[derive(Component, Clone, Copy, Debug)]
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub range: f32,
+    pub inner_angle: f32,
+    pub outer_angle: f32
+}
+
+/// Constant light applied to every surface regardless of the other lights in the scene
+pub struct AmbientLight {
+    pub color: Vec3,
+    pub intensity: f32
+}
+
+impl Default for AmbientLight {
+    fn default() -> Self {
+        Self {
+            color: Vec3::ONE,
+            intensity: 0.05
+        }
+    }
+}
+
+const DIRECTIONAL_LIGHT_KIND: f32 = 0.0;
+const POINT_LIGHT_KIND: f32 = 1.0;
+const SPOT_LIGHT_KIND: f32 = 2.0;
+
+/// The shader representation of any of the light components. The `w` components carry the light
+/// kind, range and intensity so that every light fits in the same array element.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct LightRaw {
+    position_kind: Vec4,
+    direction_range: Vec4,
+    color_intensity: Vec4,
+    cone: Vec4
+}
+
+impl From<&DirectionalLight> for LightRaw {
+    fn from(light: &DirectionalLight) -> Self {
+        Self {
+            position_kind: Vec4::new(0.0, 0.0, 0.0, DIRECTIONAL_LIGHT_KIND),
+            direction_range: light.direction.normalize().extend(0.0),
+            color_intensity: light.color.extend(light.intensity),
+            cone: Vec4::ZERO
+        }
+    }
+}
+
+impl From<&PointLight> for LightRaw {
+    fn from(light: &PointLight) -> Self {
+        Self {
+            position_kind: light.position.extend(POINT_LIGHT_KIND),
+            direction_range: Vec4::new(0.0, 0.0, 0.0, light.range),
+            color_intensity: light.color.extend(light.intensity),
+            cone: Vec4::ZERO
+        }
+    }
+}
+
+impl From<&SpotLight> for LightRaw {
+    fn from(light: &SpotLight) -> Self {
+        Self {
+            position_kind: light.position.extend(SPOT_LIGHT_KIND),
+            direction_range: light.direction.normalize().extend(light.range),
+            color_intensity: light.color.extend(light.intensity),
+            cone: Vec4::new(light.inner_angle.cos(), light.outer_angle.cos(), 0.0, 0.0)
+        }
+    }
+}
+
+/// Matches the fields preceding the light array in the shader's `Lights` storage struct
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct LightsHeader {
+    ambient: Vec4,
+    count: u32,
+    _padding: [u32; 3]
+}
+
+/// A storage buffer holding the ambient light followed by every light in the scene, grown as needed
+pub struct LightBuffer {
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    capacity: usize
+}
+
+impl LightBuffer {
+    const INITIAL_CAPACITY: usize = 16;
+
+    pub fn new(device: &Device, layout: &BindGroupLayout) -> Self {
+        Self::with_capacity(device, layout, Self::INITIAL_CAPACITY)
+    }
+
+    fn with_capacity(device: &Device, layout: &BindGroupLayout, capacity: usize) -> Self {
+        let size = std::mem::size_of::<LightsHeader>() + capacity * std::mem::size_of::<LightRaw>();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Storage Buffer"),
+            size: size as wgpu::BufferAddress,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Light Bind Group"),
+            layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: NonZeroU64::new(size as u64)
+                })
+            }]
+        });
+
+        Self { buffer, bind_group, capacity }
+    }
+
+    pub fn write(&mut self, device: &Device, queue: &Queue, layout: &BindGroupLayout, ambient_light: &AmbientLight,
+                 lights: &[LightRaw]) {
+        if lights.len() > self.capacity {
+            *self = Self::with_capacity(device, layout, lights.len().next_power_of_two());
+        }
+
+        let header = LightsHeader {
+            ambient: ambient_light.color.extend(ambient_light.intensity),
+            count: lights.len() as u32,
+            _padding: [0; 3]
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&header));
+        if !lights.is_empty() {
+            queue.write_buffer(&self.buffer, std::mem::size_of::<LightsHeader>() as wgpu::BufferAddress,
+                               bytemuck::cast_slice(lights));
+        }
+    }
+}
+
+/// Collects every light component in the world into the form the shader expects
+pub fn gather_lights<'a>(directional_lights: impl Iterator<Item = &'a DirectionalLight>,
+                         point_lights: impl Iterator<Item = &'a PointLight>,
+                         spot_lights: impl Iterator<Item = &'a SpotLight>) -> Vec<LightRaw> {
+    directional_lights.map(LightRaw::from)
+        .chain(point_lights.map(LightRaw::from))
+        .chain(spot_lights.map(LightRaw::from))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floats(bytes: &[u8]) -> Vec<f32> {
+        bytemuck::pod_collect_to_vec(bytes)
+    }
+
+    #[test]
+    fn lights_header_matches_the_std430_lights_struct() {
+        // `ambient` at 0 and `count` at 16, with the light array starting at its 16 byte alignment
+        assert_eq!(std::mem::size_of::<LightsHeader>(), 32);
+        let header = LightsHeader { ambient: Vec4::new(1.0, 2.0, 3.0, 4.0), count: 7, _padding: [0; 3] };
+        let bytes = bytemuck::bytes_of(&header);
+        assert_eq!(floats(&bytes[0..16]), [1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(u32::from_ne_bytes(bytes[16..20].try_into().unwrap()), 7);
+    }
+
+    #[test]
+    fn light_raw_matches_the_std430_light_struct() {
+        // Four vec4s at offsets 0, 16, 32 and 48, so array elements are 64 bytes apart
+        assert_eq!(std::mem::size_of::<LightRaw>(), 64);
+        let light = LightRaw::from(&SpotLight {
+            position: Vec3::new(1.0, 2.0, 3.0),
+            direction: Vec3::new(0.0, -2.0, 0.0),
+            color: Vec3::new(0.5, 0.25, 1.0),
+            intensity: 4.0,
+            range: 10.0,
+            inner_angle: 0.0,
+            outer_angle: std::f32::consts::FRAC_PI_2
+        });
+        let values = floats(bytemuck::bytes_of(&light));
+        assert_eq!(values[0..4], [1.0, 2.0, 3.0, SPOT_LIGHT_KIND]);
+        assert_eq!(values[4..8], [0.0, -1.0, 0.0, 10.0]);
+        assert_eq!(values[8..12], [0.5, 0.25, 1.0, 4.0]);
+        assert_eq!(values[12], 1.0);
+        assert!(values[13].abs() < 1e-6, "outer cone cosine was {}", values[13]);
+    }
+
+    #[test]
+    fn lights_are_gathered_by_kind() {
+        let directional = DirectionalLight { direction: Vec3::new(0.0, 0.0, -3.0), color: Vec3::ONE, intensity: 1.0 };
+        let point = PointLight { position: Vec3::X, color: Vec3::Y, intensity: 2.0, range: 5.0 };
+        let spot = SpotLight {
+            position: Vec3::Y,
+            direction: -Vec3::Y,
+            color: Vec3::Z,
+            intensity: 3.0,
+            range: 8.0,
+            inner_angle: 0.1,
+            outer_angle: 0.2
+        };
+
+        let lights = gather_lights([directional].iter(), [point, point].iter(), [spot].iter());
+        let kinds: Vec<f32> = lights.iter().map(|light| light.position_kind.w).collect();
+        assert_eq!(kinds, [DIRECTIONAL_LIGHT_KIND, POINT_LIGHT_KIND, POINT_LIGHT_KIND, SPOT_LIGHT_KIND]);
+
+        assert_eq!(lights[0].direction_range, Vec4::new(0.0, 0.0, -1.0, 0.0));
+        assert_eq!(lights[1].position_kind.truncate(), Vec3::X);
+        assert_eq!(lights[1].direction_range.w, 5.0);
+        assert_eq!(lights[1].color_intensity, Vec4::new(0.0, 1.0, 0.0, 2.0));
+        assert_eq!(lights[3].direction_range, Vec4::new(0.0, -1.0, 0.0, 8.0));
+        assert_eq!(lights[3].cone, Vec4::new(0.1_f32.cos(), 0.2_f32.cos(), 0.0, 0.0));
+    }
+}
@@ -1,20 +1,53 @@
-use glam::{Mat4, Quat, Vec3};
+use glam::{Mat3, Mat4, Quat, Vec3};
 use wgpu::{Adapter, Device, Instance, Queue, Surface, SurfaceConfiguration};
 use winit::dpi::PhysicalSize;
 use winit::window::Window;
 use bevy_ecs::prelude::*;
+use crate::app::{stage, App, Plugin, EVENT_UPDATE_SYSTEM};
+use crate::core::systems;
+use crate::core::window::WindowResized;
 
 pub mod pipeline;
 pub mod texture;
 pub mod camera;
+pub mod capture;
 pub mod instance;
+pub mod light;
 pub mod model;
+pub mod offscreen;
+
+/// Adds the render pipeline and main camera, created in the "engine_startup" stage, and draws every
+/// frame in the "render" stage. The wgpu device, queue and surface configuration, and the surface
+/// or offscreen target to draw to, have to be inserted before the first update.
+pub struct RendererPlugin;
+
+impl Plugin for RendererPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<light::AmbientLight>()
+            .init_resource::<capture::FrameCapture>()
+            .add_event::<WindowResized>()
+            .add_event::<capture::CaptureFrame>()
+            .add_system_to_stage(stage::ENGINE_STARTUP, systems::renderer_startup)
+            .add_system_to_stage(stage::FIRST, systems::resize_render_targets.after(EVENT_UPDATE_SYSTEM))
+            .add_system_to_stage(stage::FIRST, systems::resize_cameras.after(EVENT_UPDATE_SYSTEM))
+            .add_system_to_stage(stage::RENDER, systems::prepare_lights.label("prepare_lights"))
+            .add_system_to_stage(stage::RENDER, systems::update_camera_uniforms.label("update_camera_uniforms"))
+            .add_system_to_stage(stage::RENDER,
+                                 systems::render.after("prepare_lights").after("update_camera_uniforms"));
+    }
+}
 
 #[derive(Component)]
 pub struct Transform {
     pub matrix: Mat4
 }
 
+impl Default for Transform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Transform {
     pub fn new() -> Self {
         Self {
@@ -36,6 +69,12 @@ impl Transform {
             matrix
         }
     }
+
+    /// The inverse transpose of the upper 3x3, which keeps normals perpendicular to their surface
+    /// when the transform scales non-uniformly
+    pub fn normal_matrix(&self) -> Mat3 {
+        Mat3::from_mat4(self.matrix).inverse().transpose()
+    }
 }
 
 pub async fn initialize_wgpu(window: &Window) -> (Instance, Surface, Adapter, PhysicalSize<u32>){
@@ -54,14 +93,7 @@ pub async fn initialize_wgpu(window: &Window) -> (Instance, Surface, Adapter, Ph
 pub async fn initialize_renderer(adapter: &Adapter, surface: &Surface, size: &PhysicalSize<u32>)
     -> (Device, Queue, SurfaceConfiguration) {
 
-    let (device, queue) = adapter.request_device(
-        &wgpu::DeviceDescriptor {
-            features: wgpu::Features::empty(),
-            limits: wgpu::Limits::default(),
-            label: None
-        },
-        None
-    ).await.unwrap();
+    let (device, queue) = request_device(adapter).await.unwrap();
 
     let config = SurfaceConfiguration {
         usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -75,3 +107,35 @@ pub async fn initialize_renderer(adapter: &Adapter, surface: &Surface, size: &Ph
 
     (device, queue, config)
 }
+
+/// Sets up wgpu without a window or surface. Prefers a hardware adapter, falling back to a software
+/// one such as lavapipe or llvmpipe, and returns `None` if neither is available.
+pub async fn initialize_headless() -> Option<(Instance, Adapter, Device, Queue)> {
+    let instance = wgpu::Instance::new(wgpu::Backends::all());
+    let mut adapter = None;
+    for force_fallback_adapter in [false, true] {
+        adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: None,
+            force_fallback_adapter,
+        }).await;
+        if adapter.is_some() {
+            break;
+        }
+    }
+    let adapter = adapter?;
+    let (device, queue) = request_device(&adapter).await.ok()?;
+
+    Some((instance, adapter, device, queue))
+}
+
+async fn request_device(adapter: &Adapter) -> Result<(Device, Queue), wgpu::RequestDeviceError> {
+    adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            features: wgpu::Features::empty(),
+            limits: wgpu::Limits::default(),
+            label: None
+        },
+        None
+    ).await
+}
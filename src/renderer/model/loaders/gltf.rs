@@ -0,0 +1,511 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use glam::{Mat3, Mat4, Vec2, Vec3, Vec4};
+use gltf::image::Format;
+use image::{DynamicImage, ImageBuffer};
+use crate::app;
+use crate::renderer::model::{compute_flat_normals, compute_tangents, Mesh, Model, ModelLoadError, ModelNode, ModelVertex};
+use crate::renderer::model::material::{Material, MaterialProperties, MaterialTextures, ShadingModel};
+use crate::renderer::texture::{ColorSpace, Texture, TextureLoadError, TextureOptions, FLAT_NORMAL_COLOR, MISSING_TEXTURE_COLOR};
+use crate::renderer::Transform;
+
+/// The CPU side data of one glTF primitive, ready to upload
+struct PrimitiveData {
+    vertices: Vec<ModelVertex>,
+    indices: Vec<u32>,
+    /// `None` means the primitive uses the glTF default material
+    material_index: Option<usize>
+}
+
+/// A node of the scene that references a mesh, with the transform of every ancestor applied
+struct MeshNode {
+    name: Option<String>,
+    world_transform: Mat4,
+    primitives: Vec<PrimitiveData>
+}
+
+/// The parsed document with its buffers and decoded images. Images that couldn't be found are `None`
+struct ImportedGltf {
+    path: PathBuf,
+    document: gltf::Document,
+    buffers: Vec<gltf::buffer::Data>,
+    images: Vec<Option<DynamicImage>>
+}
+
+/// Like `gltf::import`, but tolerates missing image files and reports errors with the file path
+fn import(file_name: &str) -> Result<ImportedGltf, ModelLoadError> {
+    let path = Path::new(app::ASSETS_DIR).join(file_name);
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    let gltf::Gltf { document, blob } = gltf::Gltf::open(&path).map_err(|error| model_error(&path, error))?;
+    let buffers = gltf::import_buffers(&document, Some(base), blob).map_err(|error| model_error(&path, error))?;
+
+    let mut images = Vec::new();
+    for image in document.images() {
+        let label = image_label(&image);
+        match gltf::image::Data::from_source(image.source(), Some(base), &buffers) {
+            Ok(data) => images.push(Some(convert_image(&data, &label)
+                .map_err(|source| ModelLoadError::Texture { path: path.clone(), source })?)),
+            Err(gltf::Error::Io(error)) if error.kind() == io::ErrorKind::NotFound => {
+                log::warn!("Texture {} of {} is missing, using a placeholder", label, path.display());
+                images.push(None);
+            },
+            Err(error) => return Err(ModelLoadError::Texture {
+                path,
+                source: TextureLoadError::Decode { label, source: Box::new(error) }
+            })
+        }
+    }
+
+    Ok(ImportedGltf { path, document, buffers, images })
+}
+
+fn model_error(path: &Path, error: gltf::Error) -> ModelLoadError {
+    let path = path.to_path_buf();
+    match error {
+        gltf::Error::Io(source) => ModelLoadError::Io { path, source },
+        gltf::Error::UnsupportedScheme => ModelLoadError::UnsupportedFormat {
+            path,
+            reason: "a buffer uses an unsupported URI scheme".to_string()
+        },
+        error => ModelLoadError::Parse { path, source: Box::new(error) }
+    }
+}
+
+fn image_label(image: &gltf::Image) -> String {
+    match (image.name(), image.source()) {
+        (Some(name), _) => name.to_string(),
+        (None, gltf::image::Source::Uri { uri, .. }) if !uri.starts_with("data:") => uri.to_string(),
+        (None, _) => format!("gltf_image_{}", image.index())
+    }
+}
+
+/// Loads every mesh in the default scene into one model, baking each node's world transform into
+/// the vertices
+pub fn load_gltf_file(file_name: &str, device: &wgpu::Device, queue: &wgpu::Queue,
+                      layout: &wgpu::BindGroupLayout) -> Result<Model, ModelLoadError> {
+    let ImportedGltf { path, document, buffers, images } = import(file_name)?;
+    let materials = load_materials(&path, &document, &images, device, queue, layout)?;
+    let mesh_nodes = read_mesh_nodes(&document, &buffers);
+
+    let mut meshes = Vec::new();
+    for node in mesh_nodes {
+        for (index, mut primitive) in node.primitives.into_iter().enumerate() {
+            bake_transform(&mut primitive, node.world_transform);
+            meshes.push(upload_primitive(device, node.name.as_deref(), index, primitive, materials.len() - 1));
+        }
+    }
+
+    Ok(Model { meshes, materials })
+}
+
+/// Applies `transform` to the vertices of `primitive`. Normals go through the inverse transpose so
+/// that they stay perpendicular under non-uniform scale, and a mirroring transform reverses the
+/// winding of every triangle so that its front faces aren't culled.
+fn bake_transform(primitive: &mut PrimitiveData, transform: Mat4) {
+    let linear = Mat3::from_mat4(transform);
+    let normal_matrix = linear.inverse().transpose();
+    for vertex in primitive.vertices.iter_mut() {
+        vertex.position = transform.transform_point3(vertex.position);
+        vertex.normal = (normal_matrix * vertex.normal).normalize_or_zero();
+        vertex.tangent = (linear * vertex.tangent).normalize_or_zero();
+        vertex.bitangent = (linear * vertex.bitangent).normalize_or_zero();
+    }
+    if linear.determinant() < 0.0 {
+        for triangle in primitive.indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+}
+
+/// Loads each mesh node in the default scene as its own model, keeping the node's world transform
+pub fn load_gltf_scene(file_name: &str, device: &wgpu::Device, queue: &wgpu::Queue,
+                       layout: &wgpu::BindGroupLayout) -> Result<Vec<ModelNode>, ModelLoadError> {
+    let ImportedGltf { path, document, buffers, images } = import(file_name)?;
+    let materials = load_materials(&path, &document, &images, device, queue, layout)?;
+
+    Ok(read_mesh_nodes(&document, &buffers).into_iter().map(|node| {
+        let meshes = node.primitives.into_iter().enumerate()
+            .map(|(index, primitive)| upload_primitive(device, node.name.as_deref(), index, primitive, materials.len() - 1))
+            .collect();
+
+        ModelNode {
+            name: node.name,
+            model: Model { meshes, materials: materials.clone() },
+            transform: Transform::from_mat4(node.world_transform)
+        }
+    }).collect())
+}
+
+fn upload_primitive(device: &wgpu::Device, node_name: Option<&str>, index: usize, primitive: PrimitiveData,
+                    default_material_index: usize) -> Mesh {
+    Mesh::new(
+        device,
+        format!("{}_{}", node_name.unwrap_or("mesh"), index),
+        &primitive.vertices,
+        &primitive.indices,
+        primitive.material_index.unwrap_or(default_material_index)
+    )
+}
+
+/// Walks the default scene (or the first scene if none is marked as default) and collects every
+/// node with a mesh along with its world transform
+fn read_mesh_nodes(document: &gltf::Document, buffers: &[gltf::buffer::Data]) -> Vec<MeshNode> {
+    let mut mesh_nodes = Vec::new();
+    if let Some(scene) = document.default_scene().or_else(|| document.scenes().next()) {
+        for node in scene.nodes() {
+            read_node(&node, Mat4::IDENTITY, buffers, &mut mesh_nodes);
+        }
+    }
+    mesh_nodes
+}
+
+fn read_node(node: &gltf::Node, parent_transform: Mat4, buffers: &[gltf::buffer::Data], mesh_nodes: &mut Vec<MeshNode>) {
+    let world_transform = parent_transform * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+    if let Some(mesh) = node.mesh() {
+        let primitives = mesh.primitives()
+            .filter_map(|primitive| read_primitive(&primitive, buffers))
+            .collect();
+        mesh_nodes.push(MeshNode {
+            name: node.name().or_else(|| mesh.name()).map(str::to_string),
+            world_transform,
+            primitives
+        });
+    }
+
+    for child in node.children() {
+        read_node(&child, world_transform, buffers, mesh_nodes);
+    }
+}
+
+fn read_primitive(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Option<PrimitiveData> {
+    if primitive.mode() != gltf::mesh::Mode::Triangles {
+        log::warn!("Skipping glTF primitive with unsupported mode {:?}", primitive.mode());
+        return None;
+    }
+
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let positions: Vec<Vec3> = match reader.read_positions() {
+        Some(positions) => positions.map(Vec3::from).collect(),
+        None => {
+            log::warn!("Skipping glTF primitive without positions");
+            return None;
+        }
+    };
+    let indices: Vec<u32> = reader.read_indices()
+        .map(|indices| indices.into_u32().collect())
+        .unwrap_or_else(|| (0..positions.len() as u32).collect());
+    let texture_coordinates: Vec<Vec2> = reader.read_tex_coords(0)
+        .map(|coordinates| coordinates.into_f32().map(Vec2::from).collect())
+        .unwrap_or_else(|| vec![Vec2::ZERO; positions.len()]);
+    let normals: Option<Vec<Vec3>> = reader.read_normals().map(|normals| normals.map(Vec3::from).collect());
+    // Provided tangents are only meaningful together with the normals they were made for
+    let tangents = normals.as_ref().and_then(|_| reader.read_tangents());
+
+    let (positions, texture_coordinates, normals, indices) = match normals {
+        Some(normals) => (positions, texture_coordinates, normals, indices),
+        None => {
+            // A vertex shared between triangles can only have one normal, so flat shading needs
+            // every triangle to have vertices of its own
+            let positions: Vec<Vec3> = indices.iter().map(|&index| positions[index as usize]).collect();
+            let texture_coordinates = indices.iter().map(|&index| texture_coordinates[index as usize]).collect();
+            // glTF requires flat normals when a primitive doesn't provide any
+            let normals = compute_flat_normals(&positions);
+            let indices = (0..positions.len() as u32).collect();
+            (positions, texture_coordinates, normals, indices)
+        }
+    };
+
+    let mut vertices: Vec<ModelVertex> = positions.iter().zip(normals).zip(texture_coordinates)
+        .map(|((&position, normal), texture_coordinates)| ModelVertex {
+            position,
+            texture_coordinates,
+            normal,
+            tangent: Vec3::ZERO,
+            bitangent: Vec3::ZERO
+        })
+        .collect();
+
+    match tangents {
+        Some(tangents) => {
+            for (vertex, tangent) in vertices.iter_mut().zip(tangents.map(Vec4::from)) {
+                // The w component holds the handedness of the tangent space
+                vertex.tangent = tangent.truncate();
+                vertex.bitangent = vertex.normal.cross(vertex.tangent) * tangent.w;
+            }
+        },
+        None => compute_tangents(&mut vertices, &indices)
+    }
+
+    Some(PrimitiveData {
+        vertices,
+        indices,
+        material_index: primitive.material().index()
+    })
+}
+
+/// Loads every material in the document, followed by the glTF default material for primitives
+/// that don't reference one
+fn load_materials(path: &Path, document: &gltf::Document, images: &[Option<DynamicImage>], device: &wgpu::Device,
+                  queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout) -> Result<Vec<Arc<Material>>, ModelLoadError> {
+    // A missing normal map falls back to a flat one, since the magenta placeholder would bend every normal
+    let load_texture = |texture: gltf::Texture, color_space: ColorSpace, placeholder: [u8; 4]| {
+        let source = texture.source();
+        let options = sampler_options(&texture.sampler(), color_space);
+        match &images[source.index()] {
+            Some(image) => Texture::from_image(device, queue, image, Some(&image_label(&source)), options)
+                .map(Some)
+                .map_err(|source| ModelLoadError::Texture { path: path.to_path_buf(), source }),
+            None => Ok(Some(Texture::from_color(device, queue, placeholder, "missing_texture_placeholder", color_space)))
+        }
+    };
+
+    let mut materials = Vec::new();
+    for material in document.materials() {
+        let pbr = material.pbr_metallic_roughness();
+        let properties = MaterialProperties {
+            shading_model: ShadingModel::MetallicRoughness,
+            base_color_factor: Vec4::from(pbr.base_color_factor()),
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
+            occlusion_strength: material.occlusion_texture().map_or(1.0, |occlusion| occlusion.strength()),
+            emissive_factor: Vec3::from(material.emissive_factor()),
+            normal_scale: material.normal_texture().map_or(1.0, |normal| normal.scale()),
+            ..Default::default()
+        };
+
+        let textures = MaterialTextures {
+            base_color: pbr.base_color_texture()
+                .map_or(Ok(None), |info| load_texture(info.texture(), ColorSpace::Srgb, MISSING_TEXTURE_COLOR))?,
+            normal: material.normal_texture()
+                .map_or(Ok(None), |normal| load_texture(normal.texture(), ColorSpace::Linear, FLAT_NORMAL_COLOR))?,
+            metallic_roughness: pbr.metallic_roughness_texture()
+                .map_or(Ok(None), |info| load_texture(info.texture(), ColorSpace::Linear, MISSING_TEXTURE_COLOR))?,
+            occlusion: material.occlusion_texture()
+                .map_or(Ok(None), |occlusion| load_texture(occlusion.texture(), ColorSpace::Linear, MISSING_TEXTURE_COLOR))?,
+            emissive: material.emissive_texture()
+                .map_or(Ok(None), |info| load_texture(info.texture(), ColorSpace::Srgb, MISSING_TEXTURE_COLOR))?
+        };
+
+        let name = material.name().map(str::to_string)
+            .unwrap_or_else(|| format!("gltf_material_{}", material.index().unwrap_or_default()));
+        materials.push(Arc::new(Material::new(device, queue, layout, name, textures, properties)));
+    }
+
+    materials.push(Arc::new(Material::new(device, queue, layout, "gltf_default_material".to_string(),
+                                          MaterialTextures::default(), MaterialProperties::default())));
+    Ok(materials)
+}
+
+/// Translates a glTF sampler into texture options. Samplers that leave the filters undefined get
+/// trilinear filtering.
+fn sampler_options(sampler: &gltf::texture::Sampler, color_space: ColorSpace) -> TextureOptions {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+    use wgpu::FilterMode::{Linear, Nearest};
+
+    let address_mode = |wrapping_mode| match wrapping_mode {
+        WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        WrappingMode::Repeat => wgpu::AddressMode::Repeat
+    };
+    let mag_filter = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => Nearest,
+        Some(MagFilter::Linear) | None => Linear
+    };
+    // The filters without a mipmap mode only ever sample the base level
+    let (min_filter, mipmap_filter, generate_mipmaps) = match sampler.min_filter() {
+        Some(MinFilter::Nearest) => (Nearest, Nearest, false),
+        Some(MinFilter::Linear) => (Linear, Nearest, false),
+        Some(MinFilter::NearestMipmapNearest) => (Nearest, Nearest, true),
+        Some(MinFilter::LinearMipmapNearest) => (Linear, Nearest, true),
+        Some(MinFilter::NearestMipmapLinear) => (Nearest, Linear, true),
+        Some(MinFilter::LinearMipmapLinear) | None => (Linear, Linear, true)
+    };
+
+    TextureOptions {
+        color_space,
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        mag_filter,
+        min_filter,
+        mipmap_filter,
+        generate_mipmaps,
+        ..TextureOptions::default()
+    }
+}
+
+fn convert_image(data: &gltf::image::Data, label: &str) -> Result<DynamicImage, TextureLoadError> {
+    let (width, height) = (data.width, data.height);
+    let pixels = data.pixels.clone();
+    let sixteen_bit = || data.pixels.chunks_exact(2).map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]])).collect::<Vec<_>>();
+    let image = match data.format {
+        Format::R8 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        Format::R8G8 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+        Format::R8G8B8 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        Format::R8G8B8A8 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+        Format::R16 => ImageBuffer::from_raw(width, height, sixteen_bit()).map(DynamicImage::ImageLuma16),
+        Format::R16G16 => ImageBuffer::from_raw(width, height, sixteen_bit()).map(DynamicImage::ImageLumaA16),
+        Format::R16G16B16 => ImageBuffer::from_raw(width, height, sixteen_bit()).map(DynamicImage::ImageRgb16),
+        Format::R16G16B16A16 => ImageBuffer::from_raw(width, height, sixteen_bit()).map(DynamicImage::ImageRgba16),
+        _ => None
+    };
+
+    image.ok_or_else(|| TextureLoadError::UnsupportedFormat {
+        label: label.to_string(),
+        format: format!("{:?}", data.format)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A single triangle with two parent nodes, each translating by one unit along x
+    const NESTED_TRIANGLE: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [
+            { "name": "parent", "translation": [1.0, 0.0, 0.0], "children": [1] },
+            { "name": "child", "translation": [1.0, 0.0, 0.0], "mesh": 0 }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+        "accessors": [{
+            "bufferView": 0,
+            "componentType": 5126,
+            "count": 3,
+            "type": "VEC3",
+            "min": [0.0, 0.0, 0.0],
+            "max": [1.0, 1.0, 0.0]
+        }],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+        "buffers": [{
+            "byteLength": 36,
+            "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"
+        }]
+    }"#;
+
+    #[test]
+    fn node_transforms_are_combined_through_the_hierarchy() {
+        let (document, buffers, _) = gltf::import_slice(NESTED_TRIANGLE.as_bytes()).unwrap();
+        let mesh_nodes = read_mesh_nodes(&document, &buffers);
+
+        assert_eq!(mesh_nodes.len(), 1);
+        assert_eq!(mesh_nodes[0].name.as_deref(), Some("child"));
+        assert_eq!(mesh_nodes[0].world_transform, Mat4::from_translation(Vec3::new(2.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn missing_attributes_get_defaults() {
+        let (document, buffers, _) = gltf::import_slice(NESTED_TRIANGLE.as_bytes()).unwrap();
+        let primitive = &read_mesh_nodes(&document, &buffers)[0].primitives[0];
+
+        assert_eq!(primitive.indices, vec![0, 1, 2]);
+        assert_eq!(primitive.material_index, None);
+        for vertex in &primitive.vertices {
+            assert_eq!(vertex.normal, Vec3::Z);
+            assert_eq!(vertex.texture_coordinates, Vec2::ZERO);
+        }
+    }
+
+    /// Two indexed triangles facing +Z and +Y that share the vertices at the origin and on +X
+    const FOLDED_TRIANGLES: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0 }],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+        "accessors": [
+            {
+                "bufferView": 0,
+                "componentType": 5126,
+                "count": 4,
+                "type": "VEC3",
+                "min": [0.0, 0.0, 0.0],
+                "max": [1.0, 1.0, 1.0]
+            },
+            { "bufferView": 1, "componentType": 5123, "count": 6, "type": "SCALAR" }
+        ],
+        "bufferViews": [{ "buffer": 0, "byteLength": 48 }, { "buffer": 0, "byteOffset": 48, "byteLength": 12 }],
+        "buffers": [{
+            "byteLength": 60,
+            "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAAAAAAAAAAAAIA/AAABAAIAAAADAAEA"
+        }]
+    }"#;
+
+    #[test]
+    fn flat_normals_give_every_triangle_its_own_vertices() {
+        let (document, buffers, _) = gltf::import_slice(FOLDED_TRIANGLES.as_bytes()).unwrap();
+        let primitive = &read_mesh_nodes(&document, &buffers)[0].primitives[0];
+
+        assert_eq!(primitive.indices, vec![0, 1, 2, 3, 4, 5]);
+        let normals: Vec<Vec3> = primitive.vertices.iter().map(|vertex| vertex.normal).collect();
+        assert_eq!(normals, [Vec3::Z, Vec3::Z, Vec3::Z, Vec3::Y, Vec3::Y, Vec3::Y]);
+    }
+
+    fn single_vertex_triangle(normal: Vec3) -> PrimitiveData {
+        let vertex = ModelVertex {
+            position: Vec3::ZERO,
+            texture_coordinates: Vec2::ZERO,
+            normal,
+            tangent: Vec3::X,
+            bitangent: Vec3::Y
+        };
+        PrimitiveData { vertices: vec![vertex], indices: vec![0, 1, 2], material_index: None }
+    }
+
+    #[test]
+    fn baked_normals_stay_perpendicular_under_non_uniform_scale() {
+        // The surface x + y = 0 stretched along x becomes x / 2 + y = 0
+        let mut primitive = single_vertex_triangle(Vec3::new(1.0, 1.0, 0.0).normalize());
+        bake_transform(&mut primitive, Mat4::from_scale(Vec3::new(2.0, 1.0, 1.0)));
+
+        let expected = Vec3::new(0.5, 1.0, 0.0).normalize();
+        assert!(primitive.vertices[0].normal.abs_diff_eq(expected, 1e-6), "normal was {}", primitive.vertices[0].normal);
+        assert_eq!(primitive.indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn mirroring_reverses_the_winding() {
+        let mut primitive = single_vertex_triangle(Vec3::Z);
+        bake_transform(&mut primitive, Mat4::from_scale(Vec3::new(-1.0, 1.0, 1.0)));
+        assert_eq!(primitive.indices, vec![0, 2, 1]);
+        assert_eq!(primitive.vertices[0].normal, Vec3::Z);
+    }
+
+    #[test]
+    fn missing_files_are_reported_with_their_path() {
+        match import("does_not_exist.gltf") {
+            Err(ModelLoadError::Io { path, source }) => {
+                assert_eq!(path, Path::new(app::ASSETS_DIR).join("does_not_exist.gltf"));
+                assert_eq!(source.kind(), io::ErrorKind::NotFound);
+            },
+            Err(error) => panic!("unexpected error {}", error),
+            Ok(_) => panic!("loading a missing file succeeded")
+        }
+    }
+
+    #[test]
+    fn samplers_are_translated_to_texture_options() {
+        let (document, _, _) = gltf::import_slice(r#"{
+            "asset": { "version": "2.0" },
+            "samplers": [
+                { "magFilter": 9728, "minFilter": 9985, "wrapS": 33071, "wrapT": 33648 },
+                {}
+            ]
+        }"#.as_bytes()).unwrap();
+        let samplers: Vec<_> = document.samplers().collect();
+
+        let options = sampler_options(&samplers[0], ColorSpace::Linear);
+        assert_eq!(options.color_space, ColorSpace::Linear);
+        assert_eq!(options.mag_filter, wgpu::FilterMode::Nearest);
+        assert_eq!(options.min_filter, wgpu::FilterMode::Linear);
+        assert_eq!(options.mipmap_filter, wgpu::FilterMode::Nearest);
+        assert!(options.generate_mipmaps);
+        assert_eq!(options.address_mode_u, wgpu::AddressMode::ClampToEdge);
+        assert_eq!(options.address_mode_v, wgpu::AddressMode::MirrorRepeat);
+
+        let options = sampler_options(&samplers[1], ColorSpace::Srgb);
+        assert_eq!(options, TextureOptions::srgb());
+    }
+}
@@ -1 +1,2 @@
-pub mod obj;
\ No newline at end of file
+pub mod obj;
+pub mod gltf;
\ No newline at end of file
@@ -1,90 +1,300 @@
-use glam::{Vec2, Vec3};
-use wgpu::util::DeviceExt;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use glam::{Vec2, Vec3, Vec4};
+use image::DynamicImage;
 use crate::app;
-use crate::renderer::model::{Material, Mesh, Model, ModelVertex};
-use crate::renderer::texture::Texture;
+use crate::renderer::model::{compute_flat_normals, compute_tangents, Mesh, Model, ModelLoadError, ModelVertex};
+use crate::renderer::model::material::{Material, MaterialProperties, MaterialTextures, ShadingModel};
+use crate::renderer::texture::{Texture, TextureLoadError, TextureOptions, FLAT_NORMAL_COLOR, MISSING_TEXTURE_COLOR};
+
+const MTL_ANISOTROPY: u8 = 16;
+
+/// An OBJ file and its materials read into memory, before anything is uploaded to the GPU
+struct ObjData {
+    meshes: Vec<ObjMesh>,
+    materials: Vec<MtlMaterial>
+}
+
+struct ObjMesh {
+    name: String,
+    vertices: Vec<ModelVertex>,
+    indices: Vec<u32>,
+    material_index: usize
+}
+
+/// An MTL material with its texture files decoded. Missing files have already been replaced by
+/// placeholders.
+struct MtlMaterial {
+    name: String,
+    properties: MaterialProperties,
+    base_color: Option<(String, DynamicImage)>,
+    normal: Option<(String, DynamicImage)>,
+    emissive: Option<(String, DynamicImage)>
+}
 
 pub fn load_obj_file(file_name: &str, device: &wgpu::Device, queue: &wgpu::Queue,
-                     layout: &wgpu::BindGroupLayout) -> Result<Model, tobj::LoadError> {
-    let loaded_obj = tobj::load_obj(
-        format!("{}/{}", app::ASSETS_DIR, file_name),
-        &tobj::GPU_LOAD_OPTIONS
-    );
+                     layout: &wgpu::BindGroupLayout) -> Result<Model, ModelLoadError> {
+    let path = Path::new(app::ASSETS_DIR).join(file_name);
+    let obj_data = read_obj_file(file_name)?;
 
-    let (obj_models, obj_materials_result) = loaded_obj?;
-
-    let obj_materials = obj_materials_result?;
-    let mut model_materials = Vec::with_capacity(obj_materials.len());
-    for material in obj_materials {
-        let texture = Texture::load_texture(material.diffuse_texture.as_str(), device, queue).unwrap();
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture.sampler.as_ref().unwrap()),
-                }
-            ]
-        });
+    // MTL files don't describe sampling, and OBJ texture coordinates commonly tile outside 0..1
+    let color_map = TextureOptions::srgb().with_anisotropy(MTL_ANISOTROPY);
+    let data_map = TextureOptions::linear().with_anisotropy(MTL_ANISOTROPY);
+    let upload = |image: Option<(String, DynamicImage)>, options: TextureOptions| image
+        .map(|(label, image)| Texture::from_image(device, queue, &image, Some(&label), options))
+        .transpose()
+        .map_err(|source| ModelLoadError::Texture { path: path.clone(), source });
 
-        model_materials.push(Material{
-            name: material.name,
-            texture,
-            bind_group
-        })
+    let mut model_materials = Vec::with_capacity(obj_data.materials.len());
+    for material in obj_data.materials {
+        let textures = MaterialTextures {
+            base_color: upload(material.base_color, color_map)?,
+            normal: upload(material.normal, data_map)?,
+            emissive: upload(material.emissive, color_map)?,
+            ..Default::default()
+        };
+        model_materials.push(Arc::new(Material::new(device, queue, layout, material.name, textures, material.properties)))
     }
 
-    let mut model_meshes = Vec::with_capacity(obj_models.len());
-    for obj_model in obj_models {
-        let num_vertices = obj_model.mesh.positions.len() / 3;
-        let mut vertices = Vec::with_capacity(num_vertices);
-        for i in 0..num_vertices {
-            vertices.push(ModelVertex {
-                position: Vec3::new(
-                    obj_model.mesh.positions[i * 3],
-                    obj_model.mesh.positions[i * 3 + 1],
-                    obj_model.mesh.positions[i * 3 + 2],
-                ),
-                texture_coordinates: Vec2::new(
-                    obj_model.mesh.texcoords[i * 2],
-                    obj_model.mesh.texcoords[i * 2 + 1]
-                ),
-                normal: Vec3::new(
-                    obj_model.mesh.normals[i * 3],
-                    obj_model.mesh.normals[i * 3 + 1],
-                    obj_model.mesh.normals[i * 3 + 2],
-                )
-            })
+    let model_meshes = obj_data.meshes.into_iter()
+        .map(|mesh| Mesh::new(device, mesh.name, &mesh.vertices, &mesh.indices, mesh.material_index))
+        .collect();
+
+    Ok(Model {
+        meshes: model_meshes,
+        materials: model_materials
+    })
+}
+
+fn read_obj_file(file_name: &str) -> Result<ObjData, ModelLoadError> {
+    let path = Path::new(app::ASSETS_DIR).join(file_name);
+    let obj_file = File::open(&path).map_err(|source| ModelLoadError::Io { path: path.clone(), source })?;
+
+    // tobj concatenates the materials of every `mtllib` and doesn't report which library failed,
+    // so remember each library and how many materials it added while loading
+    let material_libraries: RefCell<Vec<(PathBuf, usize)>> = RefCell::new(Vec::new());
+    let failed_material_library = RefCell::new(None);
+    let loaded_obj = tobj::load_obj_buf(
+        &mut BufReader::new(obj_file),
+        &tobj::GPU_LOAD_OPTIONS,
+        |material_path| {
+            let full_path = path.parent().unwrap_or_else(|| Path::new("")).join(material_path);
+            let result = tobj::load_mtl(&full_path);
+            match &result {
+                Ok((library_materials, _)) => material_libraries.borrow_mut().push((full_path, library_materials.len())),
+                Err(_) => {
+                    failed_material_library.borrow_mut().get_or_insert(full_path);
+                }
+            }
+            result
         }
+    );
+
+    let (obj_models, obj_materials_result) = loaded_obj
+        .map_err(|source| ModelLoadError::Parse { path: path.clone(), source: Box::new(source) })?;
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::VERTEX
-        });
+    let obj_materials = obj_materials_result.map_err(|source| ModelLoadError::Parse {
+        path: failed_material_library.take().unwrap_or_else(|| path.clone()),
+        source: Box::new(source)
+    })?;
 
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&obj_model.mesh.indices),
-            usage: wgpu::BufferUsages::INDEX
-        });
+    // Texture paths in an MTL file are relative to the MTL file itself
+    let material_libraries = material_libraries.into_inner();
+    let library_paths = material_libraries.iter()
+        .flat_map(|(library_path, count)| std::iter::repeat_n(library_path, *count));
+    let mut materials = Vec::with_capacity(obj_materials.len());
+    for (material, library_path) in obj_materials.into_iter().zip(library_paths) {
+        let texture_dir = library_path.parent().unwrap_or_else(|| Path::new(""));
+        let material = read_mtl_material(material, texture_dir)
+            .map_err(|source| ModelLoadError::Texture { path: library_path.clone(), source })?;
+        materials.push(material)
+    }
 
-        model_meshes.push(Mesh {
+    // Faces before any `usemtl`, or in files without a material library, use a default material
+    // appended after the MTL ones
+    let default_material_index = materials.len();
+    let mut meshes = Vec::with_capacity(obj_models.len());
+    for obj_model in obj_models {
+        let material_index = obj_model.mesh.material_id
+            .filter(|&index| index < default_material_index)
+            .unwrap_or(default_material_index);
+        let (vertices, indices) = read_obj_mesh(obj_model.mesh);
+        meshes.push(ObjMesh {
             name: obj_model.name,
-            vertex_buffer,
-            index_buffer,
-            num_vertices: obj_model.mesh.indices.len(),
-            material_index: obj_model.mesh.material_id.unwrap_or(0)
+            vertices,
+            indices,
+            material_index
         })
     }
 
-    Ok(Model {
-        meshes: model_meshes,
-        materials: model_materials
+    if meshes.iter().any(|mesh| mesh.material_index == default_material_index) {
+        materials.push(MtlMaterial {
+            name: "obj_default_material".to_string(),
+            properties: MaterialProperties {
+                shading_model: ShadingModel::BlinnPhong,
+                metallic_factor: 0.0,
+                ..Default::default()
+            },
+            base_color: None,
+            normal: None,
+            emissive: None
+        })
+    }
+
+    Ok(ObjData { meshes, materials })
+}
+
+/// Builds the vertices of a mesh. `vt` and `vn` lines are optional in OBJ files, so texture
+/// coordinates default to zero, and meshes without normals are given flat ones.
+fn read_obj_mesh(mesh: tobj::Mesh) -> (Vec<ModelVertex>, Vec<u32>) {
+    let num_vertices = mesh.positions.len() / 3;
+    let mut vertices = Vec::with_capacity(num_vertices);
+    for i in 0..num_vertices {
+        let texture_coordinates = if mesh.texcoords.is_empty() {
+            Vec2::ZERO
+        } else {
+            Vec2::new(mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1])
+        };
+        let normal = if mesh.normals.is_empty() {
+            Vec3::ZERO
+        } else {
+            Vec3::new(mesh.normals[i * 3], mesh.normals[i * 3 + 1], mesh.normals[i * 3 + 2])
+        };
+        vertices.push(ModelVertex {
+            position: Vec3::new(mesh.positions[i * 3], mesh.positions[i * 3 + 1], mesh.positions[i * 3 + 2]),
+            texture_coordinates,
+            normal,
+            tangent: Vec3::ZERO,
+            bitangent: Vec3::ZERO
+        })
+    }
+
+    let mut indices = mesh.indices;
+    if mesh.normals.is_empty() {
+        // A vertex shared between triangles can only have one normal, so flat shading needs every
+        // triangle to have vertices of its own
+        vertices = indices.iter().map(|&index| vertices[index as usize]).collect();
+        indices = (0..vertices.len() as u32).collect();
+        let positions: Vec<Vec3> = vertices.iter().map(|vertex| vertex.position).collect();
+        for (vertex, normal) in vertices.iter_mut().zip(compute_flat_normals(&positions)) {
+            vertex.normal = normal;
+        }
+    }
+    compute_tangents(&mut vertices, &indices);
+    (vertices, indices)
+}
+
+/// Reads an MTL material and its textures. Materials using the PBR extension parameters
+/// (`Pr` roughness and `Pm` metallic) are shaded as metallic-roughness, all others as Blinn-Phong
+/// using `Ks` and `Ns`. Texture paths are resolved against `texture_dir`.
+fn read_mtl_material(material: tobj::Material, texture_dir: &Path) -> Result<MtlMaterial, TextureLoadError> {
+    let parse_scalar = |key: &str| material.unknown_param.get(key).and_then(|value| value.trim().parse::<f32>().ok());
+    let roughness = parse_scalar("Pr");
+    let metallic = parse_scalar("Pm");
+    let emissive = material.unknown_param.get("Ke")
+        .map(|value| value.split_whitespace().filter_map(|component| component.parse::<f32>().ok()).collect::<Vec<_>>())
+        .filter(|components| components.len() == 3)
+        .map(|components| Vec3::new(components[0], components[1], components[2]));
+
+    let shading_model = if roughness.is_some() || metallic.is_some() {
+        ShadingModel::MetallicRoughness
+    } else {
+        ShadingModel::BlinnPhong
+    };
+
+    // A diffuse map is drawn as is, like before materials had factors. `Kd` and `d` only color
+    // untextured materials, otherwise existing assets would change how they look.
+    let base_color_factor = if material.diffuse_texture.trim().is_empty() {
+        Vec4::from((Vec3::from(material.diffuse), material.dissolve))
+    } else {
+        Vec4::ONE
+    };
+    let properties = MaterialProperties {
+        shading_model,
+        base_color_factor,
+        metallic_factor: metallic.unwrap_or(0.0),
+        roughness_factor: roughness.unwrap_or(1.0),
+        emissive_factor: emissive.unwrap_or(Vec3::ZERO),
+        specular_color: Vec3::from(material.specular),
+        shininess: material.shininess,
+        ..Default::default()
+    };
+
+    // A missing normal map is replaced by a flat one, since the magenta placeholder would bend every normal
+    let load_image = |file_name: &str, placeholder: [u8; 4]| if file_name.trim().is_empty() {
+        Ok(None)
+    } else {
+        let file_name = file_name.trim();
+        Texture::read_image_or_placeholder(&texture_dir.join(file_name), placeholder)
+            .map(|image| Some((file_name.to_string(), image)))
+    };
+    Ok(MtlMaterial {
+        base_color: load_image(&material.diffuse_texture, MISSING_TEXTURE_COLOR)?,
+        normal: load_image(&material.normal_texture, FLAT_NORMAL_COLOR)?,
+        emissive: load_image(material.unknown_param.get("map_Ke").map_or("", String::as_str), MISSING_TEXTURE_COLOR)?,
+        name: material.name,
+        properties
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+    use super::*;
+
+    const MISSING_TEXTURES_OBJ: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/models/missing_textures.obj");
+    const RELATIVE_TEXTURE_OBJ: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/models/relative_texture.obj");
+    const POSITIONS_ONLY_OBJ: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/models/positions_only.obj");
+
+    #[test]
+    fn missing_textures_are_replaced_by_placeholders() {
+        let obj_data = read_obj_file(MISSING_TEXTURES_OBJ).unwrap();
+        assert_eq!(obj_data.meshes.len(), 1);
+        assert_eq!(obj_data.meshes[0].indices.len(), 3);
+
+        let material = &obj_data.materials[0];
+        let pixel = |image: &Option<(String, DynamicImage)>| *image.as_ref().unwrap().1.to_rgba8().get_pixel(0, 0);
+        assert_eq!(pixel(&material.base_color), Rgba(MISSING_TEXTURE_COLOR));
+        assert_eq!(pixel(&material.normal), Rgba(FLAT_NORMAL_COLOR));
+        assert!(material.emissive.is_none());
+        assert_eq!(material.properties.base_color_factor, Vec4::ONE);
+    }
+
+    #[test]
+    fn meshes_without_texture_coordinates_or_normals_are_flat_shaded() {
+        let obj_data = read_obj_file(POSITIONS_ONLY_OBJ).unwrap();
+        let mesh = &obj_data.meshes[0];
+        // The quad is triangulated and de-indexed into two triangles
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.indices, [0, 1, 2, 3, 4, 5]);
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.texture_coordinates, Vec2::ZERO);
+            assert_eq!(vertex.normal, Vec3::Z);
+            assert!(vertex.tangent.is_normalized() && vertex.tangent.dot(Vec3::Z).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn meshes_without_a_material_use_the_default_material() {
+        let obj_data = read_obj_file(POSITIONS_ONLY_OBJ).unwrap();
+        assert_eq!(obj_data.materials.len(), 1);
+        assert_eq!(obj_data.materials[0].name, "obj_default_material");
+        assert_eq!(obj_data.meshes[0].material_index, 0);
+
+        // Files whose meshes all have a material don't get one
+        let obj_data = read_obj_file(MISSING_TEXTURES_OBJ).unwrap();
+        assert_eq!(obj_data.materials.len(), 1);
+        assert_eq!(obj_data.materials[0].name, "Missing");
+    }
+
+    #[test]
+    fn textures_are_resolved_relative_to_the_material_library() {
+        let obj_data = read_obj_file(RELATIVE_TEXTURE_OBJ).unwrap();
+        let (label, image) = obj_data.materials[0].base_color.as_ref().unwrap();
+        assert_eq!(label, "relative_texture.png");
+        assert_eq!(*image.to_rgba8().get_pixel(0, 0), Rgba([0, 255, 0, 255]));
+    }
+}
@@ -0,0 +1,203 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec3, Vec4};
+use wgpu::{BindGroupLayoutEntry, BindingType, BufferBindingType, Device, Queue, ShaderStages};
+use wgpu::util::DeviceExt;
+use crate::renderer::texture::{ColorSpace, Texture};
+
+/// How the fragment shader lights a material
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShadingModel {
+    /// Blinn-Phong shading driven by the specular color and shininess, as described by MTL files
+    BlinnPhong = 0,
+    /// Physically based Cook-Torrance shading driven by the metallic and roughness inputs
+    MetallicRoughness = 1
+}
+
+pub struct Material {
+    pub name: String,
+    pub base_color_texture: Texture,
+    pub normal_texture: Texture,
+    pub metallic_roughness_texture: Texture,
+    pub occlusion_texture: Texture,
+    pub emissive_texture: Texture,
+    pub properties: MaterialProperties,
+    pub uniform_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup
+}
+
+/// The textures a material samples. Any map that isn't provided is replaced by a 1x1 texture that
+/// leaves the matching factor in `MaterialProperties` unchanged.
+#[derive(Default)]
+pub struct MaterialTextures {
+    pub base_color: Option<Texture>,
+    pub normal: Option<Texture>,
+    /// Roughness is read from the green channel and metalness from the blue channel, as in glTF
+    pub metallic_roughness: Option<Texture>,
+    /// Ambient occlusion is read from the red channel
+    pub occlusion: Option<Texture>,
+    pub emissive: Option<Texture>
+}
+
+/// The constant material factors. Each one multiplies the value sampled from the matching texture.
+#[derive(Copy, Clone, Debug)]
+pub struct MaterialProperties {
+    pub shading_model: ShadingModel,
+    pub base_color_factor: Vec4,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub occlusion_strength: f32,
+    pub emissive_factor: Vec3,
+    pub normal_scale: f32,
+    /// Only used by `ShadingModel::BlinnPhong`
+    pub specular_color: Vec3,
+    /// Only used by `ShadingModel::BlinnPhong`
+    pub shininess: f32
+}
+
+impl Default for MaterialProperties {
+    fn default() -> Self {
+        Self {
+            shading_model: ShadingModel::MetallicRoughness,
+            base_color_factor: Vec4::ONE,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            occlusion_strength: 1.0,
+            emissive_factor: Vec3::ZERO,
+            normal_scale: 1.0,
+            specular_color: Vec3::ZERO,
+            shininess: 1.0
+        }
+    }
+}
+
+/// The shader representation of `MaterialProperties`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct MaterialUniform {
+    base_color_factor: Vec4,
+    emissive_factor: Vec3,
+    metallic_factor: f32,
+    specular_color: Vec3,
+    shininess: f32,
+    roughness_factor: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    shading_model: u32
+}
+
+impl From<&MaterialProperties> for MaterialUniform {
+    fn from(properties: &MaterialProperties) -> Self {
+        Self {
+            base_color_factor: properties.base_color_factor,
+            emissive_factor: properties.emissive_factor,
+            metallic_factor: properties.metallic_factor,
+            specular_color: properties.specular_color,
+            shininess: properties.shininess,
+            roughness_factor: properties.roughness_factor,
+            occlusion_strength: properties.occlusion_strength,
+            normal_scale: properties.normal_scale,
+            shading_model: properties.shading_model as u32
+        }
+    }
+}
+
+impl Material {
+    const UNIFORM_BINDING: u32 = 2;
+    /// Texture and sampler binding pairs for the base color, normal, metallic-roughness, occlusion
+    /// and emissive maps, in that order
+    const TEXTURE_BINDINGS: [(u32, u32); 5] = [(0, 1), (3, 4), (5, 6), (7, 8), (9, 10)];
+
+    pub fn create_bind_group_layout(device: &Device) -> wgpu::BindGroupLayout {
+        let mut entries = vec![BindGroupLayoutEntry {
+            binding: Self::UNIFORM_BINDING,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<MaterialUniform>() as u64)
+            },
+            count: None,
+        }];
+        for (texture_binding, sampler_binding) in Self::TEXTURE_BINDINGS {
+            entries.push(BindGroupLayoutEntry {
+                binding: texture_binding,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            });
+            entries.push(BindGroupLayoutEntry {
+                binding: sampler_binding,
+                visibility: ShaderStages::FRAGMENT,
+                // This should match the filterable field of the
+                // corresponding Texture entry above.
+                ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("material_bind_group_layout"),
+            entries: &entries
+        })
+    }
+
+    pub fn new(device: &Device, queue: &Queue, layout: &wgpu::BindGroupLayout, name: String,
+               textures: MaterialTextures, properties: MaterialProperties) -> Self {
+        let base_color_texture = textures.base_color
+            .unwrap_or_else(|| Texture::from_color(device, queue, [255, 255, 255, 255], "default_base_color", ColorSpace::Srgb));
+        let normal_texture = textures.normal
+            .unwrap_or_else(|| Texture::flat_normal_map(device, queue));
+        let metallic_roughness_texture = textures.metallic_roughness
+            .unwrap_or_else(|| Texture::from_color(device, queue, [255, 255, 255, 255], "default_metallic_roughness", ColorSpace::Linear));
+        let occlusion_texture = textures.occlusion
+            .unwrap_or_else(|| Texture::from_color(device, queue, [255, 255, 255, 255], "default_occlusion", ColorSpace::Linear));
+        let emissive_texture = textures.emissive
+            .unwrap_or_else(|| Texture::from_color(device, queue, [255, 255, 255, 255], "default_emissive", ColorSpace::Srgb));
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Uniform Buffer"),
+            contents: bytemuck::bytes_of(&MaterialUniform::from(&properties)),
+            usage: wgpu::BufferUsages::UNIFORM
+        });
+
+        let ordered_textures = [&base_color_texture, &normal_texture, &metallic_roughness_texture,
+            &occlusion_texture, &emissive_texture];
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: Self::UNIFORM_BINDING,
+            resource: uniform_buffer.as_entire_binding(),
+        }];
+        for (texture, (texture_binding, sampler_binding)) in ordered_textures.into_iter().zip(Self::TEXTURE_BINDINGS) {
+            entries.push(wgpu::BindGroupEntry {
+                binding: texture_binding,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: sampler_binding,
+                resource: wgpu::BindingResource::Sampler(texture.sampler.as_ref().unwrap()),
+            });
+        }
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&name),
+            layout,
+            entries: &entries
+        });
+
+        Self {
+            name,
+            base_color_texture,
+            normal_texture,
+            metallic_roughness_texture,
+            occlusion_texture,
+            emissive_texture,
+            properties,
+            uniform_buffer,
+            bind_group
+        }
+    }
+}
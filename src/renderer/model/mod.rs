@@ -1,18 +1,26 @@
 use std::error::Error;
-use std::{fmt, mem};
+use std::{fmt, io, mem};
+use std::path::PathBuf;
+use std::ops::Range;
+use std::sync::Arc;
 use bytemuck::{Pod, Zeroable};
-use glam::{Vec2, Vec3};
+use glam::{Mat4, Vec2, Vec3};
 use wgpu::{BufferAddress, Device, Queue, vertex_attr_array, VertexAttribute, VertexBufferLayout, VertexStepMode};
+use wgpu::util::DeviceExt;
 use bevy_ecs::prelude::*;
 use crate::renderer::pipeline::Vertex;
-use crate::renderer::{texture, Transform};
+use crate::renderer::texture::TextureLoadError;
+use crate::renderer::Transform;
+use crate::renderer::model::material::Material;
 
 mod loaders;
+pub mod material;
 
 #[derive(Component)]
 pub struct Model {
     pub meshes: Vec<Mesh>,
-    pub materials: Vec<Material>
+    /// Shared so that every model loaded from one scene file can use the same materials
+    pub materials: Vec<Arc<Material>>
 }
 
 pub struct Mesh {
@@ -20,55 +28,235 @@ pub struct Mesh {
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_vertices: usize,
-    pub material_index: usize
+    pub material_index: usize,
+    /// The bounds of the vertices in model space
+    pub bounds: Aabb
 }
 
-pub struct Material {
-    pub name: String,
-    pub texture: texture::Texture,
-    pub bind_group: wgpu::BindGroup
+/// An axis-aligned bounding box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3
+}
+
+impl Aabb {
+    /// The smallest box containing every point, or `None` if there are no points
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        points.into_iter().fold(None, |bounds: Option<Aabb>, point| Some(match bounds {
+            Some(bounds) => Aabb { min: bounds.min.min(point), max: bounds.max.max(point) },
+            None => Aabb { min: point, max: point }
+        }))
+    }
+
+    pub fn union(self, other: Aabb) -> Aabb {
+        Aabb { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vec3::new(min.x, min.y, min.z), Vec3::new(max.x, min.y, min.z),
+            Vec3::new(min.x, max.y, min.z), Vec3::new(max.x, max.y, min.z),
+            Vec3::new(min.x, min.y, max.z), Vec3::new(max.x, min.y, max.z),
+            Vec3::new(min.x, max.y, max.z), Vec3::new(max.x, max.y, max.z)
+        ]
+    }
+
+    /// The box containing this one after it is transformed, such as from model to world space
+    pub fn transformed(&self, matrix: Mat4) -> Aabb {
+        Aabb::from_points(self.corners().map(|corner| matrix.transform_point3(corner))).unwrap()
+    }
+}
+
+impl Mesh {
+    pub fn new(device: &Device, name: String, vertices: &[ModelVertex], indices: &[u32], material_index: usize) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&name),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&name),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX
+        });
+
+        Self {
+            name,
+            vertex_buffer,
+            index_buffer,
+            num_vertices: indices.len(),
+            material_index,
+            bounds: Aabb::from_points(vertices.iter().map(|vertex| vertex.position))
+                .unwrap_or(Aabb { min: Vec3::ZERO, max: Vec3::ZERO })
+        }
+    }
+}
+
+/// One node of a loaded scene that has a mesh, with its transform resolved through the node hierarchy
+pub struct ModelNode {
+    pub name: Option<String>,
+    pub model: Model,
+    pub transform: Transform
 }
 
 impl Model {
+    /// The bounds of every mesh in model space, or `None` if the model has no meshes
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.meshes.iter().map(|mesh| mesh.bounds).reduce(Aabb::union)
+    }
+
+    /// Loads every mesh in the file into a single model. For scene formats the transform of each
+    /// node is baked into its vertices, use `load_scene` to keep nodes as separate models instead.
     pub fn load_model(model_type: ModelLoadType, file_name: &str, device: &Device, queue: &Queue,
                             layout: &wgpu::BindGroupLayout) -> Result<Model, ModelLoadError> {
-        let result = match model_type {
-            ModelLoadType::OBJ => loaders::obj::load_obj_file(file_name, device, queue, layout)
-        };
-
-        if let Ok(model) = result {
-            Ok(model)
-        } else {
-            Err(ModelLoadError)
+        match model_type {
+            ModelLoadType::OBJ => loaders::obj::load_obj_file(file_name, device, queue, layout),
+            ModelLoadType::Gltf => loaders::gltf::load_gltf_file(file_name, device, queue, layout)
+        }
+    }
+
+    /// Loads each mesh node of a scene file as its own model, paired with the node's world transform
+    /// so it can be spawned as an entity with a `Transform`. Formats without a node hierarchy produce
+    /// a single node.
+    pub fn load_scene(model_type: ModelLoadType, file_name: &str, device: &Device, queue: &Queue,
+                      layout: &wgpu::BindGroupLayout) -> Result<Vec<ModelNode>, ModelLoadError> {
+        match model_type {
+            ModelLoadType::Gltf => loaders::gltf::load_gltf_scene(file_name, device, queue, layout),
+            _ => Ok(vec![ModelNode {
+                name: Some(file_name.to_string()),
+                model: Self::load_model(model_type, file_name, device, queue, layout)?,
+                transform: Transform::new()
+            }])
         }
     }
 }
 
 pub enum ModelLoadType {
-    OBJ
+    OBJ,
+    /// glTF 2.0, either as `.gltf` JSON with embedded or external buffers, or as binary `.glb`
+    Gltf
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct ModelLoadError;
+#[derive(Debug)]
+pub enum ModelLoadError {
+    /// The model file, or a file it references such as an MTL library or external buffer, couldn't be read
+    Io { path: PathBuf, source: io::Error },
+    /// The model file was read but its contents are invalid
+    Parse { path: PathBuf, source: Box<dyn Error + Send + Sync> },
+    /// A texture referenced by the model exists but couldn't be loaded
+    Texture { path: PathBuf, source: TextureLoadError },
+    /// The model uses a feature or encoding the loader doesn't support
+    UnsupportedFormat { path: PathBuf, reason: String }
+}
 
 impl fmt::Display for ModelLoadError {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        f.write_str("Unable to load model")
+        match self {
+            ModelLoadError::Io { path, .. } => write!(f, "Unable to read model file {}", path.display()),
+            ModelLoadError::Parse { path, .. } => write!(f, "Unable to parse model {}", path.display()),
+            ModelLoadError::Texture { path, .. } => write!(f, "Unable to load a texture of model {}", path.display()),
+            ModelLoadError::UnsupportedFormat { path, reason } =>
+                write!(f, "Model {} is not supported: {}", path.display(), reason)
+        }
     }
 }
 
-
-impl Error for ModelLoadError {}
+impl Error for ModelLoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ModelLoadError::Io { source, .. } => Some(source),
+            ModelLoadError::Parse { source, .. } => Some(source.as_ref()),
+            ModelLoadError::Texture { source, .. } => Some(source),
+            ModelLoadError::UnsupportedFormat { .. } => None
+        }
+    }
+}
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct ModelVertex {
     pub position: Vec3,
     pub texture_coordinates: Vec2,
-    pub normal: Vec3
+    pub normal: Vec3,
+    pub tangent: Vec3,
+    pub bitangent: Vec3
 }
 
-const VERTEX_BUFFER_ATTRIBUTES: [VertexAttribute; 3] = vertex_attr_array![0 => Float32x3, 1 => Float32x2, 2 => Float32x3];
+const VERTEX_BUFFER_ATTRIBUTES: [VertexAttribute; 5] = vertex_attr_array![
+    0 => Float32x3,
+    1 => Float32x2,
+    2 => Float32x3,
+    3 => Float32x3,
+    4 => Float32x3
+];
+
+/// The normal of each triangle, repeated for its three vertices. `positions` has to be de-indexed,
+/// three vertices for every triangle, since a vertex shared between triangles can only have one normal.
+pub fn compute_flat_normals(positions: &[Vec3]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+    for (triangle, triangle_normals) in positions.chunks_exact(3).zip(normals.chunks_exact_mut(3)) {
+        let normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]).normalize_or_zero();
+        triangle_normals.fill(normal);
+    }
+    normals
+}
+
+/// Fills in the tangent and bitangent of every vertex from the texture coordinates of the triangles
+/// that share it, so that normal maps can be sampled in tangent space
+pub fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut triangles_per_vertex = vec![0u32; vertices.len()];
+    for vertex in vertices.iter_mut() {
+        vertex.tangent = Vec3::ZERO;
+        vertex.bitangent = Vec3::ZERO;
+    }
+
+    for triangle in indices.chunks_exact(3) {
+        let [v0, v1, v2] = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+        let edge_1 = vertices[v1].position - vertices[v0].position;
+        let edge_2 = vertices[v2].position - vertices[v0].position;
+        let delta_uv_1 = vertices[v1].texture_coordinates - vertices[v0].texture_coordinates;
+        let delta_uv_2 = vertices[v2].texture_coordinates - vertices[v0].texture_coordinates;
+
+        let determinant = delta_uv_1.x * delta_uv_2.y - delta_uv_1.y * delta_uv_2.x;
+        if determinant.abs() < f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / determinant;
+        let tangent = (edge_1 * delta_uv_2.y - edge_2 * delta_uv_1.y) * r;
+        let bitangent = (edge_2 * delta_uv_1.x - edge_1 * delta_uv_2.x) * r;
+
+        for index in [v0, v1, v2] {
+            vertices[index].tangent += tangent;
+            vertices[index].bitangent += bitangent;
+            triangles_per_vertex[index] += 1;
+        }
+    }
+
+    for (vertex, triangle_count) in vertices.iter_mut().zip(triangles_per_vertex) {
+        if triangle_count == 0 || vertex.tangent.length_squared() == 0.0 {
+            // Without usable texture coordinates, pick any basis perpendicular to the normal
+            vertex.tangent = vertex.normal.any_orthonormal_vector();
+            vertex.bitangent = vertex.normal.cross(vertex.tangent);
+            continue;
+        }
+        // Gram-Schmidt so the tangent is perpendicular to the normal, keeping the bitangent's handedness
+        let tangent = (vertex.tangent - vertex.normal * vertex.normal.dot(vertex.tangent)).normalize_or_zero();
+        let handedness = if vertex.normal.cross(tangent).dot(vertex.bitangent) < 0.0 { -1.0 } else { 1.0 };
+        vertex.tangent = tangent;
+        vertex.bitangent = vertex.normal.cross(tangent) * handedness;
+    }
+}
 
 impl Vertex for ModelVertex {
     fn buffer_layout_description<'a>() -> VertexBufferLayout<'a> {
@@ -82,16 +270,79 @@ impl Vertex for ModelVertex {
 
 pub trait DrawModel<'a> {
     fn draw_model(&mut self, model: &'a Model);
+    fn draw_model_instanced(&mut self, model: &'a Model, instances: Range<u32>);
 }
 
 impl<'a, 'b: 'a> DrawModel<'b> for wgpu::RenderPass<'a> {
     fn draw_model(&mut self, model: &'b Model) {
+        self.draw_model_instanced(model, 0..1);
+    }
+
+    /// Draws the model once per instance in `instances`. The instance buffer itself is expected to
+    /// already be bound to vertex buffer slot 1 when drawing with the instanced pipeline.
+    fn draw_model_instanced(&mut self, model: &'b Model, instances: Range<u32>) {
         for mesh in &model.meshes {
             self.set_bind_group(0, &model.materials[mesh.material_index].bind_group, &[]);
             self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-            //render_pass.set_vertex_buffer(1, render_pipeline.instance_buffer.slice(..));
             self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-            self.draw_indexed(0..mesh.num_vertices as u32, 0, 0..1);
+            self.draw_indexed(0..mesh.num_vertices as u32, 0, instances.clone());
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: Vec3, texture_coordinates: Vec2) -> ModelVertex {
+        ModelVertex {
+            position,
+            texture_coordinates,
+            normal: Vec3::Z,
+            tangent: Vec3::ZERO,
+            bitangent: Vec3::ZERO
+        }
+    }
+
+    #[test]
+    fn bounds_contain_every_transformed_corner() {
+        let bounds = Aabb::from_points([Vec3::new(-1.0, 0.0, 2.0), Vec3::new(1.0, 3.0, -2.0)]).unwrap();
+        assert_eq!(bounds, Aabb { min: Vec3::new(-1.0, 0.0, -2.0), max: Vec3::new(1.0, 3.0, 2.0) });
+        assert_eq!(bounds.center(), Vec3::new(0.0, 1.5, 0.0));
+
+        let rotated = bounds.transformed(Mat4::from_rotation_y(std::f32::consts::FRAC_PI_2));
+        assert!(rotated.min.abs_diff_eq(Vec3::new(-2.0, 0.0, -1.0), 1e-5), "min was {:?}", rotated.min);
+        assert!(rotated.max.abs_diff_eq(Vec3::new(2.0, 3.0, 1.0), 1e-5), "max was {:?}", rotated.max);
+        assert!(Aabb::from_points([]).is_none());
+    }
+
+    #[test]
+    fn tangents_follow_texture_coordinate_axes() {
+        let mut vertices = [
+            vertex(Vec3::new(0.0, 0.0, 0.0), Vec2::new(0.0, 0.0)),
+            vertex(Vec3::new(1.0, 0.0, 0.0), Vec2::new(1.0, 0.0)),
+            vertex(Vec3::new(0.0, 1.0, 0.0), Vec2::new(0.0, 1.0)),
+        ];
+        compute_tangents(&mut vertices, &[0, 1, 2]);
+
+        for vertex in vertices {
+            assert!(vertex.tangent.abs_diff_eq(Vec3::X, 1e-6), "tangent was {:?}", vertex.tangent);
+            assert!(vertex.bitangent.abs_diff_eq(Vec3::Y, 1e-6), "bitangent was {:?}", vertex.bitangent);
+        }
+    }
+
+    #[test]
+    fn mirrored_texture_coordinates_flip_the_bitangent() {
+        let mut vertices = [
+            vertex(Vec3::new(0.0, 0.0, 0.0), Vec2::new(0.0, 1.0)),
+            vertex(Vec3::new(1.0, 0.0, 0.0), Vec2::new(1.0, 1.0)),
+            vertex(Vec3::new(0.0, 1.0, 0.0), Vec2::new(0.0, 0.0)),
+        ];
+        compute_tangents(&mut vertices, &[0, 1, 2]);
+
+        for vertex in vertices {
+            assert!(vertex.tangent.abs_diff_eq(Vec3::X, 1e-6), "tangent was {:?}", vertex.tangent);
+            assert!(vertex.bitangent.abs_diff_eq(-Vec3::Y, 1e-6), "bitangent was {:?}", vertex.bitangent);
+        }
+    }
+}
@@ -1,8 +1,12 @@
-use wgpu::{Device, PipelineLayout, ShaderModule};
+use std::num::NonZeroU64;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat3, Mat4, Vec4};
+use wgpu::{BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindingResource, BufferBinding, BufferUsages, Device, PipelineLayout, Queue, ShaderModule};
 
-use crate::renderer::instance::{InstanceRaw};
-use crate::renderer::model::{ModelVertex};
+use crate::renderer::instance::InstanceBuffer;
+use crate::renderer::light::LightBuffer;
 use crate::renderer::texture::Texture;
+use crate::renderer::Transform;
 
 pub trait Vertex {
     fn buffer_layout_description<'a>() -> wgpu::VertexBufferLayout<'a>;
@@ -10,26 +14,126 @@ pub trait Vertex {
 
 pub struct RenderPipeline {
     pub wgpu_render_pipeline: wgpu::RenderPipeline,
-    pub diffuse_bind_group_layout: wgpu::BindGroupLayout,
+    pub wgpu_instanced_render_pipeline: wgpu::RenderPipeline,
+    pub material_bind_group_layout: wgpu::BindGroupLayout,
     pub depth_texture: Texture,
     pub camera_bind_group_layout: wgpu::BindGroupLayout,
     pub world_transform_bind_group_layout: wgpu::BindGroupLayout,
-    pub world_transform_bind_group: wgpu::BindGroup,
-    pub world_transform_buffer: wgpu::Buffer
+    pub world_transforms: WorldTransformBuffer,
+    pub instances: InstanceBuffer,
+    pub light_bind_group_layout: wgpu::BindGroupLayout,
+    pub lights: LightBuffer
 }
 
-pub fn create_wgpu_render_pipeline(pipeline_layout: PipelineLayout, shader: ShaderModule, device: &Device,
-                              config: &wgpu::SurfaceConfiguration) -> wgpu::RenderPipeline {
+impl RenderPipeline {
+    /// Recreates every render target whose size follows the surface
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.depth_texture = Texture::create_depth_texture(device, width, height, "depth_texture");
+    }
+}
+
+/// The shader's `WorldTransformUniform`: a world transform and its normal matrix
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct WorldTransformRaw {
+    transform_matrix: Mat4,
+    normal_matrix: [Vec4; 3]
+}
+
+impl From<&Transform> for WorldTransformRaw {
+    fn from(transform: &Transform) -> Self {
+        Self {
+            transform_matrix: transform.matrix,
+            normal_matrix: padded_columns(transform.normal_matrix())
+        }
+    }
+}
+
+/// The columns of `matrix`, each padded to a `Vec4` as GPU buffers lay out a `mat3x3`
+pub fn padded_columns(matrix: Mat3) -> [Vec4; 3] {
+    [matrix.x_axis.extend(0.0), matrix.y_axis.extend(0.0), matrix.z_axis.extend(0.0)]
+}
+
+/// A uniform buffer holding one world transform per draw call. Each transform lives in its own
+/// slot, aligned to the device's minimum uniform offset, and is selected with a dynamic offset
+/// when the world transform bind group is set.
+pub struct WorldTransformBuffer {
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    stride: wgpu::BufferAddress,
+    capacity: usize
+}
+
+impl WorldTransformBuffer {
+    const INITIAL_CAPACITY: usize = 64;
+
+    pub fn new(device: &Device, layout: &BindGroupLayout) -> Self {
+        Self::with_capacity(device, layout, Self::INITIAL_CAPACITY)
+    }
+
+    fn with_capacity(device: &Device, layout: &BindGroupLayout, capacity: usize) -> Self {
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress;
+        let transform_size = std::mem::size_of::<WorldTransformRaw>() as wgpu::BufferAddress;
+        let stride = transform_size.div_ceil(alignment) * alignment;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("World Transform Uniform Buffer"),
+            size: stride * capacity as wgpu::BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("World Transform Bind Group"),
+            layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: NonZeroU64::new(transform_size)
+                })
+            }]
+        });
+
+        Self { buffer, bind_group, stride, capacity }
+    }
+
+    /// Uploads the transforms for this frame, growing the buffer first if it has too few slots
+    pub fn write(&mut self, device: &Device, queue: &Queue, layout: &BindGroupLayout,
+                 transforms: &[WorldTransformRaw]) {
+        if transforms.len() > self.capacity {
+            *self = Self::with_capacity(device, layout, transforms.len().next_power_of_two());
+        }
+
+        let mut contents = vec![0u8; self.stride as usize * transforms.len()];
+        for (index, transform) in transforms.iter().enumerate() {
+            let start = index * self.stride as usize;
+            contents[start..start + std::mem::size_of::<WorldTransformRaw>()]
+                .copy_from_slice(bytemuck::bytes_of(transform));
+        }
+        queue.write_buffer(&self.buffer, 0, &contents);
+    }
+
+    /// The dynamic offset that selects the transform written at `index`
+    pub fn offset(&self, index: usize) -> wgpu::DynamicOffset {
+        (index as wgpu::BufferAddress * self.stride) as wgpu::DynamicOffset
+    }
+}
+
+pub fn create_wgpu_render_pipeline(label: &str, pipeline_layout: &PipelineLayout, shader: &ShaderModule,
+                              device: &Device, config: &wgpu::SurfaceConfiguration, vertex_entry_point: &str,
+                              vertex_buffers: &[wgpu::VertexBufferLayout]) -> wgpu::RenderPipeline {
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("Render Pipeline"),
-        layout: Some(&pipeline_layout),
+        label: Some(label),
+        layout: Some(pipeline_layout),
         vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: "vertex_shader_main",
-            buffers: &[ModelVertex::buffer_layout_description()],
+            module: shader,
+            entry_point: vertex_entry_point,
+            buffers: vertex_buffers,
         },
         fragment: Some(wgpu::FragmentState {
-            module: &shader,
+            module: shader,
             entry_point: "fragment_shader_main",
             targets: &[wgpu::ColorTargetState {
                 format: config.format,
@@ -64,3 +168,27 @@ pub fn create_wgpu_render_pipeline(pipeline_layout: PipelineLayout, shader: Shad
         multiview: None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+    use super::*;
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        let transform = Transform::from_mat4(Mat4::from_scale(Vec3::new(2.0, 1.0, 1.0)));
+        let tangent = Mat3::from_mat4(transform.matrix) * Vec3::new(1.0, 1.0, 0.0);
+        let normal = transform.normal_matrix() * Vec3::new(1.0, -1.0, 0.0);
+        assert!(tangent.dot(normal).abs() < 1e-6, "{:?} isn't perpendicular to {:?}", normal, tangent);
+    }
+
+    #[test]
+    fn world_transforms_pad_the_normal_matrix_columns() {
+        // mat4x4 at offset 0, then the mat3x3 with each column padded to 16 bytes
+        assert_eq!(std::mem::size_of::<WorldTransformRaw>(), 112);
+        let transform = Transform::from_mat4(Mat4::from_scale(Vec3::new(2.0, 4.0, 1.0)));
+        let values: Vec<f32> = bytemuck::pod_collect_to_vec(bytemuck::bytes_of(&WorldTransformRaw::from(&transform)));
+        assert_eq!(values[0..16], transform.matrix.to_cols_array());
+        assert_eq!(values[16..28], [0.5, 0.0, 0.0, 0.0, 0.0, 0.25, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+    }
+}
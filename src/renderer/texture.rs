@@ -1,28 +1,115 @@
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
-use std::io::Read;
-use image::{DynamicImage, GenericImageView, ImageError};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::num::{NonZeroU32, NonZeroU8};
+use image::{DynamicImage, GenericImageView, ImageBuffer, ImageError, Rgba, Rgba32FImage, RgbaImage};
+use image::error::ImageFormatHint;
+use image::imageops::{self, FilterType};
 use crate::app;
 
+/// The color of the placeholder for missing color textures
+pub const MISSING_TEXTURE_COLOR: [u8; 4] = [255, 0, 255, 255];
+/// A normal pointing straight out of the surface, encoded as a normal map texel
+pub const FLAT_NORMAL_COLOR: [u8; 4] = [128, 128, 255, 255];
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
+    pub size: wgpu::Extent3d,
     pub sampler: Option<wgpu::Sampler>,
 }
 
+/// How the texel values of a texture are interpreted when sampled
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Colors authored for display, such as base color and emissive maps. Converted to linear when sampled.
+    Srgb,
+    /// Data that isn't a color, such as normal, metallic-roughness and occlusion maps
+    Linear
+}
+
+/// How a texture is uploaded and sampled
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextureOptions {
+    pub color_space: ColorSpace,
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    /// `Linear` blends between the two closest mip levels, giving trilinear filtering
+    pub mipmap_filter: wgpu::FilterMode,
+    /// Generates the full mip chain on the CPU when the texture is created
+    pub generate_mipmaps: bool,
+    /// The maximum number of anisotropic samples: 1, 2, 4, 8 or 16. Other values are rounded to the
+    /// nearest of those. Devices without anisotropic filtering ignore it.
+    pub anisotropy: u8
+}
+
+impl TextureOptions {
+    /// Trilinear filtering with repeat addressing, for color textures
+    pub fn srgb() -> Self {
+        Self {
+            color_space: ColorSpace::Srgb,
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            generate_mipmaps: true,
+            anisotropy: 1
+        }
+    }
+
+    /// The same as `srgb` but for data textures, which must not be gamma corrected
+    pub fn linear() -> Self {
+        Self { color_space: ColorSpace::Linear, ..Self::srgb() }
+    }
+
+    pub fn with_address_mode(self, address_mode: wgpu::AddressMode) -> Self {
+        Self { address_mode_u: address_mode, address_mode_v: address_mode, ..self }
+    }
+
+    /// Sets the anisotropy, rounded to the nearest value wgpu accepts
+    pub fn with_anisotropy(self, anisotropy: u8) -> Self {
+        Self { anisotropy: valid_anisotropy(anisotropy), ..self }
+    }
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self::srgb()
+    }
+}
+
 impl Texture {
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
-    pub fn from_bytes(device: &wgpu::Device, queue: &wgpu::Queue, bytes: &[u8], label: &str)
-        -> Result<Self, ImageError> {
-        let image = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &image, Some(label))
+    pub fn from_bytes(device: &wgpu::Device, queue: &wgpu::Queue, bytes: &[u8], label: &str,
+                      options: TextureOptions) -> Result<Self, TextureLoadError> {
+        let image = decode_image(bytes, label)?;
+        Self::from_image(device, queue, &image, Some(label), options)
     }
 
-    pub fn from_image(device: &wgpu::Device, queue: &wgpu::Queue, image: &DynamicImage, label: Option<&str>)
-        -> Result<Self, ImageError> {
-        let rgba = image.to_rgba8();
+    pub fn from_image(device: &wgpu::Device, queue: &wgpu::Queue, image: &DynamicImage, label: Option<&str>,
+                      options: TextureOptions) -> Result<Self, TextureLoadError> {
         let dimensions = image.dimensions();
+        let max_dimension = device.limits().max_texture_dimension_2d;
+        if dimensions.0 > max_dimension || dimensions.1 > max_dimension {
+            return Err(TextureLoadError::ExceedsGpuLimits {
+                label: label.unwrap_or_default().to_string(),
+                width: dimensions.0,
+                height: dimensions.1,
+                max_dimension
+            });
+        }
+        let mip_levels = if options.generate_mipmaps {
+            generate_mip_chain(image.to_rgba8(), options.color_space)
+        } else {
+            vec![image.to_rgba8()]
+        };
 
         let texture_size = wgpu::Extent3d {
             width: dimensions.0,
@@ -35,11 +122,15 @@ impl Texture {
                 // All textures are stored as 3D, we represent our 2D texture
                 // by setting depth to 1.
                 size: texture_size,
-                mip_level_count: 1,
+                mip_level_count: mip_levels.len() as u32,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                // Most images are stored using sRGB so we need to reflect that here.
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                // Most images are stored using sRGB so we need to reflect that here. Data textures such
+                // as normal, roughness and occlusion maps are not colors, so they are sampled linearly.
+                format: match options.color_space {
+                    ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+                    ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm
+                },
                 // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
                 // COPY_DST means that we want to copy data to this texture
                 usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
@@ -47,50 +138,102 @@ impl Texture {
             }
         );
 
-        queue.write_texture(
-            // Tells wgpu where to copy the pixel data
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            // The actual pixel data
-            &rgba,
-            // The layout of the texture
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * dimensions.0),
-                rows_per_image: std::num::NonZeroU32::new(dimensions.1),
-            },
-            texture_size,
-        );
+        for (mip_level, level) in mip_levels.iter().enumerate() {
+            queue.write_texture(
+                // Tells wgpu where to copy the pixel data
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                // The actual pixel data
+                level,
+                // The layout of the texture
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(4 * level.width()),
+                    rows_per_image: NonZeroU32::new(level.height()),
+                },
+                wgpu::Extent3d {
+                    width: level.width(),
+                    height: level.height(),
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_u: options.address_mode_u,
+            address_mode_v: options.address_mode_v,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            mag_filter: options.mag_filter,
+            min_filter: options.min_filter,
+            mipmap_filter: options.mipmap_filter,
+            anisotropy_clamp: NonZeroU8::new(valid_anisotropy(options.anisotropy)).filter(|clamp| clamp.get() > 1),
             ..Default::default()
         });
 
-        Ok(Self { texture, view, sampler: Some(sampler), })
+        Ok(Self { texture, view, size: texture_size, sampler: Some(sampler), })
+    }
+
+    pub fn load_texture(file_name: &str, device: &wgpu::Device, queue: &wgpu::Queue,
+                        options: TextureOptions) -> Result<Texture, TextureLoadError> {
+        let image = Texture::load_image(file_name)?;
+        Texture::from_image(device, queue, &image, Some(file_name), options)
     }
 
-    pub fn load_texture(file_name: &str, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Texture, ImageError> {
-        let mut texture_file = File::open(format!("{}/{}", app::ASSETS_DIR, file_name))?;
+    /// Reads and decodes an image from the assets directory without uploading it
+    pub fn load_image(file_name: &str) -> Result<DynamicImage, TextureLoadError> {
+        Texture::read_image(&Path::new(app::ASSETS_DIR).join(file_name))
+    }
+
+    /// Reads and decodes an image at `path`, which isn't resolved against the assets directory
+    pub fn read_image(path: &Path) -> Result<DynamicImage, TextureLoadError> {
+        let io_error = |source: io::Error| if source.kind() == io::ErrorKind::NotFound {
+            TextureLoadError::Missing { path: path.to_path_buf(), source }
+        } else {
+            TextureLoadError::Io { path: path.to_path_buf(), source }
+        };
+
+        let mut texture_file = File::open(path).map_err(io_error)?;
         let mut bytes = Vec::new();
-        texture_file.read_to_end(&mut bytes)?;
-        Texture::from_bytes(device, queue, bytemuck::cast_slice(&bytes),  file_name)
+        texture_file.read_to_end(&mut bytes).map_err(io_error)?;
+        decode_image(&bytes, &path.display().to_string())
     }
 
-    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+    /// Reads an image at `path`, substituting a 1x1 image of `placeholder` with a warning if the
+    /// file doesn't exist. Any other failure is still returned as an error.
+    pub fn read_image_or_placeholder(path: &Path, placeholder: [u8; 4]) -> Result<DynamicImage, TextureLoadError> {
+        match Texture::read_image(path) {
+            Err(TextureLoadError::Missing { path, .. }) => {
+                log::warn!("Texture {} is missing, using a placeholder", path.display());
+                Ok(DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba(placeholder))))
+            },
+            result => result
+        }
+    }
+
+    /// A 1x1 texture of a single color, used in place of material maps that a model doesn't provide
+    pub fn from_color(device: &wgpu::Device, queue: &wgpu::Queue, color: [u8; 4], label: &str,
+                      color_space: ColorSpace) -> Texture {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba(color)));
+        let options = TextureOptions { color_space, ..TextureOptions::default() };
+        Texture::from_image(device, queue, &image, Some(label), options)
+            .expect("Unable to create single color texture")
+    }
+
+    /// A 1x1 normal map pointing straight out of the surface, for materials without a normal map
+    /// and in place of missing ones, which would otherwise bend every normal
+    pub fn flat_normal_map(device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+        Texture::from_color(device, queue, FLAT_NORMAL_COLOR, "flat_normal_map", ColorSpace::Linear)
+    }
+
+    pub fn create_depth_texture(device: &wgpu::Device, width: u32, height: u32, label: &str) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1
         };
 
@@ -106,6 +249,194 @@ impl Texture {
         let texture = device.create_texture(&descriptor);
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        Self { texture, view, sampler: None }
+        Self { texture, view, size, sampler: None }
+    }
+}
+
+/// The number of mip levels needed to halve the largest dimension down to a single pixel
+fn mip_level_count(width: u32, height: u32) -> u32 {
+    u32::BITS - width.max(height).max(1).leading_zeros()
+}
+
+/// The anisotropy values wgpu accepts for `anisotropy_clamp`
+const ANISOTROPY_LEVELS: [u8; 5] = [1, 2, 4, 8, 16];
+
+/// Rounds to the nearest accepted anisotropy, preferring the lower one between two
+fn valid_anisotropy(anisotropy: u8) -> u8 {
+    ANISOTROPY_LEVELS.into_iter()
+        .min_by_key(|level| level.abs_diff(anisotropy))
+        .unwrap()
+}
+
+/// Every mip level of the image, starting with the image itself. Each level is filtered down from
+/// the one before it in linear space, so that sRGB textures don't darken as they get smaller.
+fn generate_mip_chain(image: RgbaImage, color_space: ColorSpace) -> Vec<RgbaImage> {
+    let level_count = mip_level_count(image.width(), image.height());
+    let to_linear = |value: u8| match color_space {
+        ColorSpace::Srgb => srgb_to_linear(value),
+        ColorSpace::Linear => value as f32 / 255.0
+    };
+    let from_linear = |value: f32| match color_space {
+        ColorSpace::Srgb => linear_to_srgb(value),
+        ColorSpace::Linear => (value.clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+
+    // Filtering from the full precision level above keeps rounding errors from adding up
+    let mut linear: Rgba32FImage = ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+        Rgba([to_linear(r), to_linear(g), to_linear(b), a as f32 / 255.0])
+    });
+    let mut levels = Vec::with_capacity(level_count as usize);
+    levels.push(image);
+    for _ in 1..level_count {
+        let width = (linear.width() / 2).max(1);
+        let height = (linear.height() / 2).max(1);
+        linear = imageops::resize(&linear, width, height, FilterType::Triangle);
+        levels.push(ImageBuffer::from_fn(width, height, |x, y| {
+            let Rgba([r, g, b, a]) = *linear.get_pixel(x, y);
+            Rgba([from_linear(r), from_linear(g), from_linear(b), (a.clamp(0.0, 1.0) * 255.0).round() as u8])
+        }));
+    }
+    levels
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+/// Decodes an image in any format the `image` crate was built with, telling files in a format it
+/// doesn't support apart from corrupt ones
+fn decode_image(bytes: &[u8], label: &str) -> Result<DynamicImage, TextureLoadError> {
+    image::load_from_memory(bytes).map_err(|error| match error {
+        ImageError::Unsupported(unsupported) => TextureLoadError::UnsupportedFormat {
+            label: label.to_string(),
+            format: match unsupported.format_hint() {
+                ImageFormatHint::Unknown => "unknown".to_string(),
+                hint => hint.to_string()
+            }
+        },
+        error => TextureLoadError::Decode { label: label.to_string(), source: Box::new(error) }
+    })
+}
+
+#[derive(Debug)]
+pub enum TextureLoadError {
+    /// The texture file doesn't exist
+    Missing { path: PathBuf, source: io::Error },
+    /// The texture file exists but couldn't be read
+    Io { path: PathBuf, source: io::Error },
+    /// The texture data couldn't be decoded as an image
+    Decode { label: String, source: Box<dyn Error + Send + Sync> },
+    /// The image is in a file format the `image` crate can't decode, or was decoded to a pixel
+    /// format that can't be uploaded
+    UnsupportedFormat { label: String, format: String },
+    /// The image is larger than the device allows for 2D textures
+    ExceedsGpuLimits { label: String, width: u32, height: u32, max_dimension: u32 }
+}
+
+impl fmt::Display for TextureLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            TextureLoadError::Missing { path, .. } => write!(f, "Texture {} does not exist", path.display()),
+            TextureLoadError::Io { path, .. } => write!(f, "Unable to read texture {}", path.display()),
+            TextureLoadError::Decode { label, .. } => write!(f, "Unable to decode texture {}", label),
+            TextureLoadError::UnsupportedFormat { label, format } =>
+                write!(f, "Texture {} has unsupported format {}", label, format),
+            TextureLoadError::ExceedsGpuLimits { label, width, height, max_dimension } =>
+                write!(f, "Texture {} is {}x{} but the GPU supports at most {}x{}",
+                       label, width, height, max_dimension, max_dimension)
+        }
     }
-}
\ No newline at end of file
+}
+
+impl Error for TextureLoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TextureLoadError::Missing { source, .. } | TextureLoadError::Io { source, .. } => Some(source),
+            TextureLoadError::Decode { source, .. } => Some(source.as_ref()),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_chain_halves_down_to_one_pixel() {
+        let levels = generate_mip_chain(RgbaImage::new(16, 4), ColorSpace::Linear);
+        let sizes: Vec<_> = levels.iter().map(|level| level.dimensions()).collect();
+        assert_eq!(sizes, [(16, 4), (8, 2), (4, 1), (2, 1), (1, 1)]);
+        assert_eq!(mip_level_count(16, 4), 5);
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(300, 17), 9);
+    }
+
+    fn checkerboard() -> RgbaImage {
+        let mut checkerboard = RgbaImage::new(2, 2);
+        for (x, y, pixel) in checkerboard.enumerate_pixels_mut() {
+            let value = if (x + y) % 2 == 0 { 255 } else { 0 };
+            *pixel = Rgba([value, value, value, 255]);
+        }
+        checkerboard
+    }
+
+    #[test]
+    fn mip_levels_average_the_level_above() {
+        let levels = generate_mip_chain(checkerboard(), ColorSpace::Linear);
+        let smallest = levels.last().unwrap().get_pixel(0, 0);
+        assert!((120..=135).contains(&smallest[0]), "expected mid grey, got {:?}", smallest);
+        assert_eq!(smallest[3], 255);
+    }
+
+    #[test]
+    fn srgb_mip_levels_average_in_linear_space() {
+        let levels = generate_mip_chain(checkerboard(), ColorSpace::Srgb);
+        let smallest = levels.last().unwrap().get_pixel(0, 0);
+        // Half of full intensity is 188 in sRGB, where averaging the encoded values gives 128
+        assert!((183..=193).contains(&smallest[0]), "expected sRGB mid grey, got {:?}", smallest);
+        assert_eq!(smallest[3], 255);
+    }
+
+    #[test]
+    fn anisotropy_is_rounded_to_a_valid_value() {
+        let anisotropy = |value| TextureOptions::srgb().with_anisotropy(value).anisotropy;
+        assert_eq!(anisotropy(0), 1);
+        assert_eq!(anisotropy(3), 2);
+        assert_eq!(anisotropy(5), 4);
+        assert_eq!(anisotropy(7), 8);
+        assert_eq!(anisotropy(16), 16);
+        assert_eq!(anisotropy(200), 16);
+    }
+
+    #[test]
+    fn unrecognized_image_formats_are_unsupported_rather_than_corrupt() {
+        match decode_image(b"not an image", "text") {
+            Err(TextureLoadError::UnsupportedFormat { label, format }) => {
+                assert_eq!(label, "text");
+                assert_eq!(format, "unknown");
+            },
+            result => panic!("expected an unsupported format, got {:?}", result.map(|image| image.dimensions()))
+        }
+
+        // A PNG signature followed by garbage is a PNG that fails to decode
+        let corrupt_png = b"\x89PNG\r\n\x1a\ngarbage";
+        assert!(matches!(decode_image(corrupt_png, "corrupt"), Err(TextureLoadError::Decode { .. })));
+    }
+}
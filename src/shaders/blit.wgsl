@@ -0,0 +1,15 @@
+// Copies a texture onto a target of the same size and format with a single triangle covering it
+
+[[group(0), binding(0)]]
+var frame: texture_2d<f32>;
+
+[[stage(vertex)]]
+fn vertex_shader_main([[builtin(vertex_index)]] vertex_index: u32) -> [[builtin(position)]] vec4<f32> {
+    let corner = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+}
+
+[[stage(fragment)]]
+fn fragment_shader_main([[builtin(position)]] position: vec4<f32>) -> [[location(0)]] vec4<f32> {
+    return textureLoad(frame, vec2<i32>(position.xy), 0);
+}
@@ -1,9 +1,12 @@
 struct CameraUniform {
     view_projection_matrix: mat4x4<f32>;
+    view_position: vec4<f32>;
 };
 
 struct WorldTransformUniform {
     transform_matrix: mat4x4<f32>;
+    // The inverse transpose of the transform's upper 3x3
+    normal_matrix: mat3x3<f32>;
 };
 
 [[group(1), binding(0)]]
@@ -14,30 +17,247 @@ var<uniform> world: WorldTransformUniform;
 struct VertexInput {
     [[location(0)]] position: vec3<f32>;
     [[location(1)]] texture_coordinates: vec2<f32>;
+    [[location(2)]] normal: vec3<f32>;
+    [[location(3)]] tangent: vec3<f32>;
+    [[location(4)]] bitangent: vec3<f32>;
+};
+
+struct InstanceInput {
+    [[location(5)]] transform_matrix_0: vec4<f32>;
+    [[location(6)]] transform_matrix_1: vec4<f32>;
+    [[location(7)]] transform_matrix_2: vec4<f32>;
+    [[location(8)]] transform_matrix_3: vec4<f32>;
+    [[location(9)]] normal_matrix_0: vec4<f32>;
+    [[location(10)]] normal_matrix_1: vec4<f32>;
+    [[location(11)]] normal_matrix_2: vec4<f32>;
 };
 
 
 struct VertexOutput {
     [[builtin(position)]] clip_position: vec4<f32>;
     [[location(0)]] texture_coordinates: vec2<f32>;
+    [[location(1)]] world_position: vec3<f32>;
+    [[location(2)]] world_normal: vec3<f32>;
+    [[location(3)]] world_tangent: vec3<f32>;
+    [[location(4)]] world_bitangent: vec3<f32>;
 };
 
+fn transform_vertex(model: VertexInput, transform_matrix: mat4x4<f32>, normal_matrix: mat3x3<f32>) -> VertexOutput {
+    // Tangents lie in the surface, so they move with it rather than with the normal matrix
+    let linear_matrix = mat3x3<f32>(
+        transform_matrix[0].xyz,
+        transform_matrix[1].xyz,
+        transform_matrix[2].xyz,
+    );
+    let world_position = transform_matrix * vec4<f32>(model.position, 1.0);
+
+    var outVertex: VertexOutput;
+    outVertex.texture_coordinates = model.texture_coordinates;
+    outVertex.world_position = world_position.xyz;
+    outVertex.world_normal = normalize(normal_matrix * model.normal);
+    outVertex.world_tangent = normalize(linear_matrix * model.tangent);
+    outVertex.world_bitangent = normalize(linear_matrix * model.bitangent);
+    outVertex.clip_position = camera.view_projection_matrix * world_position;
+    return outVertex;
+}
+
 [[stage(vertex)]]
 fn vertex_shader_main(
     model: VertexInput,
 ) -> VertexOutput {
-    var outVertex: VertexOutput;
-    outVertex.texture_coordinates = model.texture_coordinates;
-    outVertex.clip_position = camera.view_projection_matrix * world.transform_matrix * vec4<f32>(model.position, 1.0);
-    return outVertex;
+    return transform_vertex(model, world.transform_matrix, world.normal_matrix);
 }
 
+[[stage(vertex)]]
+fn vertex_shader_instanced_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let transform_matrix = mat4x4<f32>(
+        instance.transform_matrix_0,
+        instance.transform_matrix_1,
+        instance.transform_matrix_2,
+        instance.transform_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0.xyz,
+        instance.normal_matrix_1.xyz,
+        instance.normal_matrix_2.xyz,
+    );
+    return transform_vertex(model, transform_matrix, normal_matrix);
+}
+
+struct MaterialUniform {
+    base_color_factor: vec4<f32>;
+    emissive_factor: vec3<f32>;
+    metallic_factor: f32;
+    specular_color: vec3<f32>;
+    shininess: f32;
+    roughness_factor: f32;
+    occlusion_strength: f32;
+    normal_scale: f32;
+    shading_model: u32;
+};
+
+let BLINN_PHONG: u32 = 0u;
+let METALLIC_ROUGHNESS: u32 = 1u;
+
 [[group(0), binding(0)]]
-var t_diffuse: texture_2d<f32>;
+var t_base_color: texture_2d<f32>;
 [[group(0), binding(1)]]
-var s_diffuse: sampler;
+var s_base_color: sampler;
+[[group(0), binding(2)]]
+var<uniform> material: MaterialUniform;
+[[group(0), binding(3)]]
+var t_normal: texture_2d<f32>;
+[[group(0), binding(4)]]
+var s_normal: sampler;
+[[group(0), binding(5)]]
+var t_metallic_roughness: texture_2d<f32>;
+[[group(0), binding(6)]]
+var s_metallic_roughness: sampler;
+[[group(0), binding(7)]]
+var t_occlusion: texture_2d<f32>;
+[[group(0), binding(8)]]
+var s_occlusion: sampler;
+[[group(0), binding(9)]]
+var t_emissive: texture_2d<f32>;
+[[group(0), binding(10)]]
+var s_emissive: sampler;
+
+let DIRECTIONAL_LIGHT: f32 = 0.0;
+let POINT_LIGHT: f32 = 1.0;
+let SPOT_LIGHT: f32 = 2.0;
+
+struct Light {
+    // w is the kind of light
+    position: vec4<f32>;
+    // w is the range of point and spot lights
+    direction: vec4<f32>;
+    // w is the intensity
+    color: vec4<f32>;
+    // x and y are the cosines of the inner and outer spot light angles
+    cone: vec4<f32>;
+};
+
+struct Lights {
+    // w is the intensity
+    ambient: vec4<f32>;
+    count: u32;
+    lights: array<Light>;
+};
+
+[[group(3), binding(0)]]
+var<storage, read> lights: Lights;
+
+let PI: f32 = 3.14159265359;
+
+/// The direction from the surface towards the light
+fn light_direction(light: Light, world_position: vec3<f32>) -> vec3<f32> {
+    if (light.position.w == DIRECTIONAL_LIGHT) {
+        return -normalize(light.direction.xyz);
+    }
+    return normalize(light.position.xyz - world_position);
+}
+
+/// The light's color and intensity after distance and spot cone falloff
+fn light_radiance(light: Light, world_position: vec3<f32>, direction_to_light: vec3<f32>) -> vec3<f32> {
+    var attenuation = 1.0;
+    if (light.position.w != DIRECTIONAL_LIGHT) {
+        let distance = length(light.position.xyz - world_position);
+        let falloff = clamp(1.0 - pow(distance / light.direction.w, 4.0), 0.0, 1.0);
+        attenuation = falloff * falloff / (distance * distance + 1.0);
+    }
+    if (light.position.w == SPOT_LIGHT) {
+        let theta = dot(direction_to_light, -normalize(light.direction.xyz));
+        attenuation = attenuation * clamp((theta - light.cone.y) / (light.cone.x - light.cone.y), 0.0, 1.0);
+    }
+    return light.color.rgb * light.color.w * attenuation;
+}
+
+/// Trowbridge-Reitz GGX normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let alpha = roughness * roughness;
+    let alpha_squared = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha_squared - 1.0) + 1.0;
+    return alpha_squared / (PI * denominator * denominator);
+}
+
+/// Smith's method with the Schlick-GGX approximation for direct lighting
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let view_term = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let light_term = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return view_term * light_term;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+fn blinn_phong(normal: vec3<f32>, view_direction: vec3<f32>, direction_to_light: vec3<f32>,
+               base_color: vec3<f32>) -> vec3<f32> {
+    let diffuse_strength = max(dot(normal, direction_to_light), 0.0);
+    // Surfaces facing away from the light get no highlight, even where the half vector still faces them
+    var specular_strength = 0.0;
+    if (diffuse_strength > 0.0) {
+        let half_direction = normalize(direction_to_light + view_direction);
+        specular_strength = pow(max(dot(normal, half_direction), 0.0), material.shininess);
+    }
+    return diffuse_strength * base_color + specular_strength * material.specular_color;
+}
+
+fn cook_torrance(normal: vec3<f32>, view_direction: vec3<f32>, direction_to_light: vec3<f32>,
+                 base_color: vec3<f32>, metallic: f32, roughness: f32) -> vec3<f32> {
+    let half_direction = normalize(direction_to_light + view_direction);
+    let n_dot_l = max(dot(normal, direction_to_light), 0.0);
+    let n_dot_v = max(dot(normal, view_direction), 0.0001);
+    let n_dot_h = max(dot(normal, half_direction), 0.0);
+
+    // Dielectrics reflect about 4% of light head on, metals reflect their base color
+    let f0 = mix(vec3<f32>(0.04), base_color, metallic);
+    let fresnel = fresnel_schlick(max(dot(half_direction, view_direction), 0.0), f0);
+    let specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness) * fresnel
+        / (4.0 * n_dot_v * n_dot_l + 0.0001);
+    let diffuse = (vec3<f32>(1.0) - fresnel) * (1.0 - metallic) * base_color / PI;
+
+    return (diffuse + specular) * n_dot_l;
+}
 
 [[stage(fragment)]]
 fn fragment_shader_main(inputVertex: VertexOutput) -> [[location(0)]] vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, inputVertex.texture_coordinates);
+    let uv = inputVertex.texture_coordinates;
+    let base_color = textureSample(t_base_color, s_base_color, uv) * material.base_color_factor;
+    let metallic_roughness = textureSample(t_metallic_roughness, s_metallic_roughness, uv);
+    let metallic = clamp(metallic_roughness.b * material.metallic_factor, 0.0, 1.0);
+    // Fully smooth surfaces make the GGX highlight infinitely small, so keep a little roughness
+    let roughness = clamp(metallic_roughness.g * material.roughness_factor, 0.04, 1.0);
+    let occlusion = 1.0 + material.occlusion_strength * (textureSample(t_occlusion, s_occlusion, uv).r - 1.0);
+    let emissive = textureSample(t_emissive, s_emissive, uv).rgb * material.emissive_factor;
+
+    let tangent_to_world = mat3x3<f32>(
+        normalize(inputVertex.world_tangent),
+        normalize(inputVertex.world_bitangent),
+        normalize(inputVertex.world_normal),
+    );
+    let tangent_normal = textureSample(t_normal, s_normal, uv).xyz * 2.0 - 1.0;
+    let scaled_tangent_normal = vec3<f32>(tangent_normal.xy * material.normal_scale, tangent_normal.z);
+    let normal = normalize(tangent_to_world * scaled_tangent_normal);
+    let view_direction = normalize(camera.view_position.xyz - inputVertex.world_position);
+
+    var color = lights.ambient.rgb * lights.ambient.w * base_color.rgb * occlusion;
+    for (var i: u32 = 0u; i < lights.count; i = i + 1u) {
+        let light = lights.lights[i];
+        let direction_to_light = light_direction(light, inputVertex.world_position);
+        let radiance = light_radiance(light, inputVertex.world_position, direction_to_light);
+
+        if (material.shading_model == METALLIC_ROUGHNESS) {
+            color = color + radiance * cook_torrance(normal, view_direction, direction_to_light, base_color.rgb,
+                                                     metallic, roughness);
+        } else {
+            color = color + radiance * blinn_phong(normal, view_direction, direction_to_light, base_color.rgb);
+        }
+    }
+
+    return vec4<f32>(color + emissive, base_color.a);
 }